@@ -1,9 +1,12 @@
-use aper::connection::{MessageToClient, MessageToServer, ServerConnection, ServerHandle};
-use aper::{Aper, IntentMetadata};
+use aper::codec::Frame;
+use aper::connection::{ServerConnection, ServerHandle};
+use aper::scheduler::Scheduler;
+use aper::Aper;
 use chrono::Utc;
 pub use stateroom::ClientId;
 use stateroom::{MessagePayload, StateroomContext, StateroomService};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 pub struct AperStateroomService<P>
 where
@@ -11,11 +14,8 @@ where
     P::Intent: Unpin + 'static,
 {
     connection: ServerConnection<P>,
-    suspended_event: Option<(P::Intent, IntentMetadata)>,
+    scheduler: Scheduler<P>,
     client_connections: HashMap<ClientId, ServerHandle<P>>,
-
-    /// Pseudo-connection for sending timer events.
-    timer_event_handle: ServerHandle<P>,
 }
 
 impl<P: Aper> Default for AperStateroomService<P>
@@ -24,15 +24,7 @@ where
     P::Intent: Unpin + 'static,
 {
     fn default() -> Self {
-        let mut connection = ServerConnection::new();
-        let timer_event_handle = connection.connect(|_| {});
-
-        AperStateroomService {
-            connection,
-            suspended_event: None,
-            client_connections: HashMap::new(),
-            timer_event_handle,
-        }
+        Self::from_connection(ServerConnection::new())
     }
 }
 
@@ -41,33 +33,41 @@ where
     P: Aper,
     P::Intent: Unpin + 'static,
 {
-    fn update_suspended_event(&mut self, ctx: &impl StateroomContext) {
-        let susp = self.connection.state().suspended_event();
-        if susp == self.suspended_event {
-            return;
-        }
-
-        if let Some(ev) = &susp {
-            let dur = ev.1.timestamp.signed_duration_since(Utc::now());
-            ctx.set_timer(dur.num_milliseconds().max(0) as u32);
+    /// Serve an existing connection, e.g. one configured with
+    /// [`ServerConnection::set_presence_interval`].
+    pub fn from_connection(connection: ServerConnection<P>) -> Self {
+        AperStateroomService {
+            connection,
+            scheduler: Scheduler::new(),
+            client_connections: HashMap::new(),
         }
+    }
 
-        self.suspended_event = susp;
+    /// Re-read the scheduled events, and set the room's timer.
+    fn update_scheduled_events(&mut self, ctx: &impl StateroomContext) {
+        self.scheduler.update(&self.connection.state());
+        self.set_timer(ctx);
     }
 
-    fn process_message(
-        &mut self,
-        message: MessageToServer,
-        client_id: Option<ClientId>,
-        ctx: &impl StateroomContext,
-    ) {
-        if let Some(handle) = client_id.and_then(|id| self.client_connections.get_mut(&id)) {
-            handle.receive(&message);
-        } else {
-            self.timer_event_handle.receive(&message);
+    /// Set the room's timer for whichever comes first of the next scheduled event and the
+    /// next presence update that is being held back.
+    fn set_timer(&self, ctx: &impl StateroomContext) {
+        let now = Instant::now();
+        let presence = self
+            .client_connections
+            .values()
+            .filter_map(|handle| handle.next_presence_flush())
+            .min()
+            .map(|at| at.saturating_duration_since(now));
+        let event = self.scheduler.next_event().map(|next| {
+            next.signed_duration_since(Utc::now())
+                .to_std()
+                .unwrap_or_default()
+        });
+
+        if let Some(delay) = presence.into_iter().chain(event).min() {
+            ctx.set_timer(timer_millis(delay));
         }
-
-        self.update_suspended_event(ctx);
     }
 }
 
@@ -77,22 +77,28 @@ where
     P::Intent: Unpin + Send + Sync + 'static,
 {
     fn init(&mut self, ctx: &impl StateroomContext) {
-        self.update_suspended_event(ctx);
+        self.update_scheduled_events(ctx);
     }
 
     fn connect(&mut self, client_id: ClientId, ctx: &impl StateroomContext) {
         let ctx = Clone::clone(ctx);
-        let callback = move |message: &MessageToClient| {
-            ctx.send_message(client_id, bincode::serialize(&message).unwrap());
+        let callback = move |frame: Frame| {
+            let payload = match frame {
+                Frame::Text(text) => MessagePayload::Text(text),
+                Frame::Binary(bytes) => MessagePayload::Bytes(bytes),
+            };
+            ctx.send_message(client_id, payload);
         };
 
-        let handle = self.connection.connect(callback);
+        let handle = self.connection.connect_framed(callback);
 
         self.client_connections.insert(client_id, handle);
     }
 
-    fn disconnect(&mut self, user: ClientId, _ctx: &impl StateroomContext) {
+    fn disconnect(&mut self, user: ClientId, ctx: &impl StateroomContext) {
+        // The state may react to the client leaving.
         self.client_connections.remove(&user);
+        self.update_scheduled_events(ctx);
     }
 
     fn message(
@@ -101,30 +107,33 @@ where
         message: MessagePayload,
         ctx: &impl StateroomContext,
     ) {
-        match message {
-            MessagePayload::Text(txt) => {
-                let message: MessageToServer = serde_json::from_str(&txt).unwrap();
-                self.process_message(message, Some(client_id), ctx);
-            }
-            MessagePayload::Bytes(bytes) => {
-                let message: MessageToServer = bincode::deserialize(&bytes).unwrap();
-                self.process_message(message, Some(client_id), ctx);
+        let frame = match message {
+            MessagePayload::Text(txt) => Frame::Text(txt),
+            MessagePayload::Bytes(bytes) => Frame::Binary(bytes),
+        };
+
+        // A malformed message is reported to its sender, and does not affect the room.
+        if let Some(handle) = self.client_connections.get_mut(&client_id) {
+            if handle.receive_frame(&frame).is_err() && handle.is_disconnected() {
+                self.client_connections.remove(&client_id);
             }
         }
+
+        self.update_scheduled_events(ctx);
     }
 
     fn timer(&mut self, ctx: &impl StateroomContext) {
-        if let Some(mut event) = self.suspended_event.take() {
-            event.1.timestamp = Utc::now();
-            let event = bincode::serialize(&event).unwrap();
-            self.process_message(
-                MessageToServer::Intent {
-                    intent: event,
-                    client_version: 0,
-                },
-                None,
-                ctx,
-            );
+        for handle in self.client_connections.values_mut() {
+            handle.flush_presence();
         }
+
+        // Rejected events are logged by the scheduler and not retried.
+        self.scheduler.run_due(&self.connection, Utc::now());
+        self.set_timer(ctx);
     }
 }
+
+/// Rounds up, so that the timer does not fire just before the deadline.
+fn timer_millis(delay: Duration) -> u32 {
+    delay.as_micros().div_ceil(1000).min(u32::MAX.into()) as u32
+}
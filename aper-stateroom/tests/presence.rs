@@ -0,0 +1,163 @@
+use aper::{
+    codec::{Frame, FramedMessage, WireFormat},
+    connection::{ClientConnection, MessageToClient, MessageToServer, ServerConnection},
+    data_structures::Atom,
+    Aper, AperClient, AperSync, IntentMetadata,
+};
+use aper_stateroom::AperStateroomService;
+use serde::{Deserialize, Serialize};
+use stateroom::{ClientId, MessagePayload, MessageRecipient, StateroomContext, StateroomService};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(AperSync, Clone)]
+struct Document {
+    body: Atom<String>,
+}
+
+impl Aper for Document {
+    type Intent = String;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &String, _metadata: &IntentMetadata) -> Result<(), ()> {
+        self.body.set(intent.clone());
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct Cursor {
+    position: usize,
+}
+
+/// A room that records the messages sent by the service and the timer it set.
+#[derive(Clone, Default)]
+struct Room {
+    messages: Arc<Mutex<Vec<(ClientId, MessagePayload)>>>,
+    timer: Arc<Mutex<Option<u32>>>,
+}
+
+impl StateroomContext for Room {
+    fn send_message(
+        &self,
+        recipient: impl Into<MessageRecipient>,
+        message: impl Into<MessagePayload>,
+    ) {
+        let MessageRecipient::Client(client_id) = recipient.into() else {
+            panic!("Expected a message to a single client.");
+        };
+        self.messages
+            .lock()
+            .unwrap()
+            .push((client_id, message.into()));
+    }
+
+    fn set_timer(&self, ms_delay: u32) {
+        *self.timer.lock().unwrap() = Some(ms_delay);
+    }
+}
+
+struct Peer {
+    client_id: ClientId,
+    connection: ClientConnection<Document>,
+    to_server: Arc<Mutex<Vec<MessageToServer>>>,
+}
+
+impl Peer {
+    fn connect(service: &mut AperStateroomService<Document>, room: &Room, client_id: u32) -> Self {
+        let client_id = ClientId(client_id);
+        service.connect(client_id, room);
+
+        let to_server: Arc<Mutex<Vec<MessageToServer>>> = Arc::default();
+        let queue = to_server.clone();
+        let connection = ClientConnection::new(AperClient::new(), move |message| {
+            queue.lock().unwrap().push(message)
+        });
+
+        Peer {
+            client_id,
+            connection,
+            to_server,
+        }
+    }
+
+    /// Sends the messages queued by the client to the service.
+    fn send(&mut self, service: &mut AperStateroomService<Document>, room: &Room) {
+        let messages: Vec<_> = self.to_server.lock().unwrap().drain(..).collect();
+        for message in messages {
+            let payload = match message.encode_frame(WireFormat::default()).unwrap() {
+                Frame::Text(text) => MessagePayload::Text(text),
+                Frame::Binary(bytes) => MessagePayload::Bytes(bytes),
+            };
+            service.message(self.client_id, payload, room);
+        }
+    }
+
+    /// Receives the messages the service sent to this client.
+    fn receive(&mut self, room: &Room) {
+        let mut messages = room.messages.lock().unwrap();
+        let (mine, others) = messages
+            .drain(..)
+            .partition(|(client_id, _)| *client_id == self.client_id);
+        *messages = others;
+        drop(messages);
+
+        for (_, payload) in mine {
+            let frame = match payload {
+                MessagePayload::Text(text) => Frame::Text(text),
+                MessagePayload::Bytes(bytes) => Frame::Binary(bytes),
+            };
+            let message = MessageToClient::decode_frame(&frame, WireFormat::default()).unwrap();
+            self.connection.receive(&message);
+        }
+    }
+
+    fn id(&self) -> u32 {
+        self.connection.client_id().unwrap()
+    }
+}
+
+#[test]
+fn throttled_presence_is_sent_without_further_activity() {
+    let mut connection = ServerConnection::<Document>::new();
+    connection.set_presence_interval(Duration::from_millis(50));
+    let mut service = AperStateroomService::from_connection(connection);
+    let room = Room::default();
+    service.init(&room);
+
+    let mut alice = Peer::connect(&mut service, &room, 1);
+    let mut bob = Peer::connect(&mut service, &room, 2);
+    alice.send(&mut service, &room);
+    bob.send(&mut service, &room);
+    alice.receive(&room);
+    bob.receive(&room);
+    assert_eq!(*room.timer.lock().unwrap(), None);
+
+    for position in 0..5 {
+        alice.connection.set_presence(&Cursor { position });
+        alice.send(&mut service, &room);
+    }
+    bob.receive(&room);
+    assert_eq!(
+        bob.connection.peer_presence::<Cursor>(),
+        BTreeMap::from([(alice.id(), Cursor { position: 0 })])
+    );
+
+    // Alice goes quiet; the room's timer sends her last update.
+    let delay = room.timer.lock().unwrap().take().unwrap();
+    assert!(delay <= 50);
+    std::thread::sleep(Duration::from_millis(delay.into()));
+    service.timer(&room);
+    bob.receive(&room);
+
+    assert_eq!(
+        bob.connection.peer_presence::<Cursor>(),
+        BTreeMap::from([(alice.id(), Cursor { position: 4 })])
+    );
+    assert_eq!(*room.timer.lock().unwrap(), None);
+}
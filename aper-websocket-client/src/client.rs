@@ -1,14 +1,173 @@
-use crate::typed::TypedWebsocketConnection;
+use crate::{
+    reconnect::ReconnectConfig,
+    status::{ConnectionStatus, StatusListeners},
+    typed::{Outbox, TypedWebsocketConnection},
+};
 use anyhow::Result;
 use aper::{
-    connection::{ClientConnection, MessageToClient, MessageToServer},
-    Aper, AperClient, Store,
+    codec::WireFormat,
+    connection::{
+        ClientConnection, ClientInfo, HandshakeRejection, MessageToClient, MessageToServer,
+    },
+    Aper, AperClient, Denied, Store,
 };
 use core::fmt::Debug;
+use serde::{de::DeserializeOwned, Serialize};
 use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
     rc::{Rc, Weak},
     sync::Mutex,
 };
+use wasm_bindgen::{closure::Closure, JsCast};
+
+/// Opens sockets for an [`AperWebSocketClient`], and reopens them when they close.
+struct Connector<S: Aper> {
+    url: String,
+    config: ReconnectConfig,
+    wire_format: WireFormat,
+    outbox: Outbox<MessageToServer>,
+    conn: RefCell<Weak<Mutex<ClientConnection<S>>>>,
+
+    /// The number of consecutive reconnection attempts that have failed.
+    attempt: Cell<u32>,
+
+    status: Cell<ConnectionStatus>,
+    /// The status and number of pending intents as of the last time listeners were
+    /// alerted.
+    notified: Cell<(ConnectionStatus, u64)>,
+    listeners: StatusListeners,
+}
+
+impl<S: Aper> Connector<S> {
+    fn set_status(&self, status: ConnectionStatus) {
+        self.status.set(status);
+        self.alert();
+    }
+
+    /// Alerts listeners if the status or the number of pending intents has changed since
+    /// they were last alerted. The connection must not be locked.
+    fn alert(&self) {
+        let Some(conn) = self.conn.borrow().upgrade() else {
+            return;
+        };
+        let pending_intents = conn.lock().unwrap().pending_intents();
+
+        let status = (self.status.get(), pending_intents);
+        if status == self.notified.get() {
+            return;
+        }
+        self.notified.set(status);
+
+        self.listeners.alert();
+    }
+
+    /// Opens a socket, returning a callback that sends messages on it.
+    fn connect(self: &Rc<Self>) -> Result<Box<dyn Fn(MessageToServer)>> {
+        let this = self.clone();
+        let socket_message_callback = move |message: MessageToClient| {
+            let Some(conn) = this.conn.borrow().upgrade() else {
+                return;
+            };
+            conn.lock().unwrap().receive(&message);
+            this.alert();
+        };
+
+        let this = self.clone();
+        let on_open = move || {
+            this.attempt.set(0);
+            this.set_status(ConnectionStatus::Open);
+        };
+
+        let this = self.clone();
+        let on_close = move || this.schedule_reconnect();
+
+        let wss_conn = TypedWebsocketConnection::new(
+            &self.url,
+            socket_message_callback,
+            self.outbox.clone(),
+            self.wire_format,
+            on_open,
+            on_close,
+        )?;
+
+        Ok(Box::new(move |message: MessageToServer| {
+            wss_conn.send(&message);
+        }))
+    }
+
+    fn schedule_reconnect(self: &Rc<Self>) {
+        let attempt = self.attempt.get();
+        let Some(delay) = self.config.delay(attempt, js_sys::Math::random()) else {
+            tracing::error!(attempt, "giving up on reconnecting");
+            self.set_status(ConnectionStatus::Closed);
+            return;
+        };
+        self.attempt.set(attempt + 1);
+        self.set_status(ConnectionStatus::Reconnecting {
+            attempt: attempt + 1,
+        });
+
+        tracing::info!(?delay, attempt, "connection closed; reconnecting");
+
+        let this = self.clone();
+        let callback = Closure::once_into_js(move || this.reconnect());
+        let result = web_sys::window()
+            .expect("Reconnecting requires a window.")
+            .set_timeout_with_callback_and_timeout_and_arguments_0(
+                callback.unchecked_ref(),
+                delay.as_millis() as i32,
+            );
+
+        if let Err(err) = result {
+            tracing::error!(?err, "failed to schedule reconnect");
+        }
+    }
+
+    fn reconnect(self: &Rc<Self>) {
+        // The client may have been dropped while waiting.
+        let Some(conn) = self.conn.borrow().upgrade() else {
+            return;
+        };
+
+        match self.connect() {
+            Ok(callback) => resume(&self.outbox, &mut conn.lock().unwrap(), callback),
+            Err(err) => {
+                tracing::warn!(?err, "failed to open socket");
+                self.schedule_reconnect();
+            }
+        }
+    }
+}
+
+/// Resumes `conn`'s session over a new connection, keeping the messages queued in `outbox`
+/// while it was offline.
+///
+/// Queued intents are dropped, since resuming re-sends every intent the server has not
+/// confirmed, and so is the previous connection's handshake, which the new one replaces.
+/// The rest (e.g. presence updates) are sent after the new handshake.
+fn resume<S: Aper, F: Fn(MessageToServer) + 'static>(
+    outbox: &Outbox<MessageToServer>,
+    conn: &mut ClientConnection<S>,
+    callback: F,
+) {
+    let unsent: Vec<_> = outbox
+        .lock()
+        .drain(..)
+        .filter(|message| {
+            !matches!(
+                message,
+                MessageToServer::Handshake { .. }
+                    | MessageToServer::RequestState { .. }
+                    | MessageToServer::Resume { .. }
+                    | MessageToServer::Intent { .. }
+            )
+        })
+        .collect();
+
+    conn.reconnect(callback);
+    outbox.lock().extend(unsent);
+}
 
 #[derive(Clone)]
 pub struct AperWebSocketClient<S>
@@ -16,6 +175,7 @@ where
     S: Aper,
 {
     conn: Rc<Mutex<ClientConnection<S>>>,
+    connector: Rc<Connector<S>>,
 }
 
 impl<T> PartialEq for AperWebSocketClient<T>
@@ -41,34 +201,53 @@ impl<S> AperWebSocketClient<S>
 where
     S: Aper,
 {
+    /// Connects to `url`, reconnecting with the default [`ReconnectConfig`] if the
+    /// connection drops.
     pub fn new(url: &str) -> Result<Self> {
-        // callback is called when the state changes
-        // need to create a connection
-        // connection needs to be able to call the state and message callback
+        Self::with_reconnect(url, ReconnectConfig::default())
+    }
 
-        // client message handler needs to have websocket connection; websocket
-        // connection needs to be able to send messages to client
+    /// Connects to `url`, reconnecting according to `config` if the connection drops.
+    ///
+    /// When a connection is re-established, the client resumes its session: it catches
+    /// up on changes it missed and re-sends intents the server did not receive.
+    pub fn with_reconnect(url: &str, config: ReconnectConfig) -> Result<Self> {
+        Self::with_options(url, config, WireFormat::default())
+    }
 
+    /// Connects to `url`, reconnecting according to `config` and encoding messages in
+    /// `wire_format`.
+    pub fn with_options(
+        url: &str,
+        config: ReconnectConfig,
+        wire_format: WireFormat,
+    ) -> Result<Self> {
         let client = AperClient::<S>::new();
 
-        let conn = Rc::new_cyclic(|c: &Weak<Mutex<ClientConnection<S>>>| {
-            let d = c.clone();
-            let socket_message_callback = move |message: MessageToClient| {
-                let d = d.upgrade().unwrap();
-                let mut conn = d.lock().unwrap();
-                conn.receive(&message);
-            };
-
-            let wss_conn = TypedWebsocketConnection::new(url, socket_message_callback).unwrap();
+        let connector = Rc::new(Connector {
+            url: url.to_string(),
+            config,
+            wire_format,
+            outbox: Outbox::default(),
+            conn: RefCell::new(Weak::new()),
+            attempt: Cell::new(0),
+            status: Cell::new(ConnectionStatus::Connecting),
+            notified: Cell::new((ConnectionStatus::Connecting, 0)),
+            listeners: StatusListeners::default(),
+        });
 
-            let message_callback = Box::new(move |message: MessageToServer| {
-                wss_conn.send(&message);
-            });
+        let message_callback = connector.connect()?;
+        let conn = Rc::new(Mutex::new(ClientConnection::with_wire_format(
+            client,
+            wire_format,
+            message_callback,
+        )));
 
-            Mutex::new(ClientConnection::new(client, message_callback))
-        });
+        // Socket events are delivered by the browser's event loop, so none can arrive
+        // before this is set.
+        *connector.conn.borrow_mut() = Rc::downgrade(&conn);
 
-        Ok(AperWebSocketClient { conn })
+        Ok(AperWebSocketClient { conn, connector })
     }
 
     pub fn store(&self) -> Store {
@@ -81,12 +260,207 @@ where
     }
 
     pub fn apply(&self, intent: S::Intent) -> Result<(), S::Error> {
-        let mut conn = self.conn.lock().unwrap();
+        let result = self.conn.lock().unwrap().apply(intent);
+        self.connector.alert();
 
-        conn.apply(intent)
+        result
     }
 
     pub fn client_id(&self) -> Option<u32> {
         self.conn.lock().unwrap().client_id()
     }
+
+    /// Why the server rejected the connection's handshake, if it did; e.g. because this
+    /// client was built against an older schema and the page must be reloaded.
+    pub fn handshake_rejection(&self) -> Option<HandshakeRejection> {
+        self.conn.lock().unwrap().handshake_rejection().cloned()
+    }
+
+    /// The number of intents that have been applied locally but not yet handed to the
+    /// socket, e.g. because it has not opened yet or is reconnecting.
+    pub fn unsent_intents(&self) -> usize {
+        let queued = self
+            .connector
+            .outbox
+            .lock()
+            .iter()
+            .filter(|message| matches!(message, MessageToServer::Intent { .. }))
+            .count();
+
+        queued + self.conn.lock().unwrap().held_intents()
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        self.connector.status.get()
+    }
+
+    /// The number of intents applied locally that the server has not yet confirmed.
+    pub fn pending_intents(&self) -> u64 {
+        self.conn.lock().unwrap().pending_intents()
+    }
+
+    /// Calls `listener` whenever [`Self::status`] or [`Self::pending_intents`] changes,
+    /// until it returns `false`.
+    pub fn listen<F: Fn() -> bool + 'static>(&self, listener: F) {
+        self.connector.listeners.listen(listener)
+    }
+
+    /// Share `presence` with the other clients. See [`ClientConnection::set_presence`].
+    pub fn set_presence<P: Serialize>(&self, presence: &P) {
+        self.conn.lock().unwrap().set_presence(presence)
+    }
+
+    pub fn clear_presence(&self) {
+        self.conn.lock().unwrap().clear_presence()
+    }
+
+    /// The presence of every other connected client that has set one, by client ID.
+    pub fn peer_presence<P: DeserializeOwned>(&self) -> BTreeMap<u32, P> {
+        self.conn.lock().unwrap().peer_presence()
+    }
+
+    /// Calls `listener` whenever the presence of another client changes, until it returns
+    /// `false`. Like [`Self::on_rejected`], the listener must not call back into the client.
+    pub fn listen_presence<F: Fn() -> bool + 'static>(&self, listener: F) {
+        self.conn.lock().unwrap().listen_presence(listener)
+    }
+
+    /// The clients connected to the server, if it broadcasts its roster.
+    pub fn roster(&self) -> Vec<ClientInfo> {
+        self.conn.lock().unwrap().roster().to_vec()
+    }
+
+    /// Calls `callback` with each intent from this client that the server denies, and the
+    /// reason. Like [`Self::on_rejected`], the callback must not call back into the client.
+    pub fn on_denied<F: Fn(&S::Intent, &Denied) + 'static>(&self, callback: F) {
+        self.conn.lock().unwrap().on_denied(callback)
+    }
+}
+
+impl<S> AperWebSocketClient<S>
+where
+    S: Aper,
+    S::Error: DeserializeOwned,
+{
+    /// Calls `callback` with each intent from this client that the server rejects, and
+    /// the error it was rejected with. See [`ClientConnection::on_rejected`].
+    ///
+    /// The callback runs while the connection is locked, so it must not call back into
+    /// the client; defer any new intents (e.g. with `spawn_local`).
+    pub fn on_rejected<F: Fn(&S::Intent, S::Error) + 'static>(&self, callback: F) {
+        self.conn.lock().unwrap().on_rejected(callback)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aper::{
+        connection::{MessageToClientType, ServerConnection, ServerHandle},
+        data_structures::Atom,
+        AperSync, IntentMetadata,
+    };
+    use serde::Deserialize;
+    use std::sync::Arc;
+
+    #[derive(AperSync, Clone)]
+    struct Counter {
+        value: Atom<u32>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+    struct Increment;
+
+    impl Aper for Counter {
+        type Intent = Increment;
+        type Error = ();
+
+        const SCHEMA_VERSION: u32 = 1;
+
+        fn apply(&mut self, _intent: &Increment, _metadata: &IntentMetadata) -> Result<(), ()> {
+            self.value.set(self.value.get() + 1);
+            Ok(())
+        }
+    }
+
+    type Inbox = Arc<Mutex<Vec<MessageToClient>>>;
+
+    fn connect(server: &mut ServerConnection<Counter>) -> (ServerHandle<Counter>, Inbox) {
+        let inbox = Inbox::default();
+        let inbox_ = inbox.clone();
+        let handle = server.connect(move |message| inbox_.lock().unwrap().push(message.clone()));
+        (handle, inbox)
+    }
+
+    fn queue(outbox: &Outbox<MessageToServer>) -> impl Fn(MessageToServer) + 'static {
+        let outbox = outbox.clone();
+        move |message| outbox.lock().push_back(message)
+    }
+
+    /// Passes messages back and forth until neither side has any more to send, as if the
+    /// socket were open.
+    fn exchange(
+        outbox: &Outbox<MessageToServer>,
+        handle: &mut ServerHandle<Counter>,
+        inbox: &Inbox,
+        conn: &mut ClientConnection<Counter>,
+    ) {
+        while !outbox.lock().is_empty() {
+            let messages: Vec<_> = outbox.lock().drain(..).collect();
+            for message in messages {
+                handle.receive(&message).unwrap();
+            }
+
+            let messages: Vec<_> = inbox.lock().unwrap().drain(..).collect();
+            for message in messages {
+                conn.receive(&message);
+            }
+        }
+    }
+
+    #[test]
+    fn messages_queued_while_offline_are_sent_after_reconnecting() {
+        let mut server = ServerConnection::<Counter>::new();
+        let (_observer, observed) = connect(&mut server);
+
+        let outbox = Outbox::default();
+        let (mut handle, inbox) = connect(&mut server);
+        let mut conn = ClientConnection::new(AperClient::<Counter>::new(), queue(&outbox));
+        exchange(&outbox, &mut handle, &inbox, &mut conn);
+
+        // The socket closes, and messages are queued until it reopens.
+        drop(handle);
+        conn.apply(Increment).unwrap();
+        conn.set_presence(&"typing");
+        conn.apply(Increment).unwrap();
+        assert_eq!(outbox.lock().len(), 3);
+
+        let (mut handle, inbox) = connect(&mut server);
+        resume(&outbox, &mut conn, queue(&outbox));
+        assert_eq!(conn.held_intents(), 2);
+
+        // The presence update is kept, after the new handshake. The intents are re-sent
+        // once the session resumes.
+        let queued: Vec<_> = outbox.lock().iter().cloned().collect();
+        assert!(matches!(
+            queued.as_slice(),
+            [
+                MessageToServer::Handshake { .. },
+                MessageToServer::Resume { .. },
+                MessageToServer::SetPresence { presence: Some(_) },
+            ]
+        ));
+
+        observed.lock().unwrap().clear();
+        exchange(&outbox, &mut handle, &inbox, &mut conn);
+
+        assert_eq!(server.state().value.get(), 2);
+        assert_eq!(conn.state().value.get(), 2);
+        assert_eq!(conn.pending_intents(), 0);
+        assert!(observed.lock().unwrap().iter().any(|message| matches!(
+            &message.message,
+            MessageToClientType::Presence { presence: Some(presence), .. }
+                if presence == "\"typing\""
+        )));
+    }
 }
@@ -1,5 +1,11 @@
 mod client;
+#[cfg(feature = "native")]
+pub mod native;
+mod reconnect;
+mod status;
 mod typed;
 mod websocket;
 
 pub use client::AperWebSocketClient;
+pub use reconnect::ReconnectConfig;
+pub use status::ConnectionStatus;
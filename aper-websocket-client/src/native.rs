@@ -0,0 +1,256 @@
+use anyhow::Result;
+use aper::{
+    codec::{Frame, FramedMessage, WireFormat},
+    connection::{ClientConnection, MessageToClient},
+    Aper, AperClient, Store,
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    fmt::{Debug, Display},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{
+    mpsc::{self, error::SendError},
+    oneshot,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+enum Command<S: Aper> {
+    Apply(S::Intent, oneshot::Sender<Result<(), S::Error>>),
+    Read(Box<dyn FnOnce(&S) + Send>),
+}
+
+/// The reason [`AperWebSocketClient::apply`] failed.
+#[derive(Debug)]
+pub enum ApplyError<E> {
+    /// The intent could not be applied to the local state.
+    Rejected(E),
+    /// The socket has closed. This client does not reconnect.
+    Disconnected,
+}
+
+impl<E: Debug> Display for ApplyError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApplyError::Rejected(error) => write!(f, "Intent was rejected: {:?}", error),
+            ApplyError::Disconnected => write!(f, "Connection to the server was closed."),
+        }
+    }
+}
+
+impl<E: Debug> std::error::Error for ApplyError<E> {}
+
+/// A client for native (non-wasm) targets, e.g. for bots and integration tests.
+///
+/// The connection runs on a background thread with its own tokio runtime, so the client
+/// can be used from any async runtime. Unlike the browser client, it does not reconnect:
+/// once the socket closes, [`AperWebSocketClient::apply`] returns
+/// [`ApplyError::Disconnected`], and a new client must be created to continue.
+pub struct AperWebSocketClient<S: Aper> {
+    commands: mpsc::UnboundedSender<Command<S>>,
+    store: Store,
+    client_id: Arc<Mutex<Option<u32>>>,
+}
+
+impl<S: Aper> Clone for AperWebSocketClient<S> {
+    fn clone(&self) -> Self {
+        Self {
+            commands: self.commands.clone(),
+            store: self.store.clone(),
+            client_id: self.client_id.clone(),
+        }
+    }
+}
+
+impl<S> AperWebSocketClient<S>
+where
+    S: Aper,
+    S::Intent: Send,
+    S::Error: Send,
+{
+    /// Connects to `url`, returning once the socket is open.
+    pub async fn new(url: &str) -> Result<Self> {
+        Self::with_wire_format(url, WireFormat::default()).await
+    }
+
+    /// Connects to `url`, encoding messages in `wire_format`.
+    pub async fn with_wire_format(url: &str, wire_format: WireFormat) -> Result<Self> {
+        let url = url.to_string();
+        let (ready, connected) = oneshot::channel();
+        let (commands, command_receiver) = mpsc::unbounded_channel();
+        let client_id = Arc::new(Mutex::new(None));
+
+        let client_id_ = client_id.clone();
+        std::thread::spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(err) => {
+                    let _ = ready.send(Err(err.into()));
+                    return;
+                }
+            };
+
+            runtime.block_on(run::<S>(
+                url,
+                wire_format,
+                ready,
+                command_receiver,
+                client_id_,
+            ));
+        });
+
+        let store = connected.await??;
+
+        Ok(AperWebSocketClient {
+            commands,
+            store,
+            client_id,
+        })
+    }
+
+    /// The store of the local state.
+    ///
+    /// The connection's thread updates the store as messages arrive, so reads from it are
+    /// not isolated from those updates: e.g. while local intents are rebased onto one from
+    /// the server, the state briefly lacks them. Use [`AperWebSocketClient::read`] to
+    /// read a consistent state.
+    pub fn store(&self) -> Store {
+        self.store.clone()
+    }
+
+    /// A live view of the local state. See [`AperWebSocketClient::store`] for why its
+    /// reads may be inconsistent.
+    pub fn state(&self) -> S {
+        S::attach(self.store.handle())
+    }
+
+    /// Calls `f` with the local state on the connection's thread, between updates, so
+    /// that everything it reads is from the same version of the state.
+    pub async fn read<T: Send + 'static>(&self, f: impl FnOnce(&S) -> T + Send + 'static) -> T {
+        let (reply, result) = oneshot::channel();
+        let read: Box<dyn FnOnce(&S) + Send> = Box::new(move |state| {
+            let _ = reply.send(f(state));
+        });
+
+        if let Err(SendError(Command::Read(read))) = self.commands.send(Command::Read(read)) {
+            // The connection has closed, so the state is no longer updated.
+            read(&self.state());
+        }
+
+        result
+            .await
+            .expect("Connection thread panicked while reading the state.")
+    }
+
+    /// Apply an intent to the local state, and send it to the server.
+    pub async fn apply(&self, intent: S::Intent) -> Result<(), ApplyError<S::Error>> {
+        let (reply, result) = oneshot::channel();
+
+        self.commands
+            .send(Command::Apply(intent, reply))
+            .map_err(|_| ApplyError::Disconnected)?;
+
+        match result.await {
+            Ok(result) => result.map_err(ApplyError::Rejected),
+            Err(_) => Err(ApplyError::Disconnected),
+        }
+    }
+
+    /// Whether the socket is still open.
+    pub fn is_connected(&self) -> bool {
+        !self.commands.is_closed()
+    }
+
+    pub fn client_id(&self) -> Option<u32> {
+        *self.client_id.lock().unwrap()
+    }
+}
+
+/// Runs the connection until the socket closes or every clone of the client is dropped.
+async fn run<S: Aper>(
+    url: String,
+    wire_format: WireFormat,
+    ready: oneshot::Sender<Result<Store>>,
+    mut commands: mpsc::UnboundedReceiver<Command<S>>,
+    client_id: Arc<Mutex<Option<u32>>>,
+) {
+    let socket = match tokio_tungstenite::connect_async(url.as_str()).await {
+        Ok((socket, _)) => socket,
+        Err(err) => {
+            let _ = ready.send(Err(err.into()));
+            return;
+        }
+    };
+    let (mut sink, mut stream) = socket.split();
+
+    let (outbound_sender, mut outbound) = mpsc::unbounded_channel();
+    let mut conn =
+        ClientConnection::with_wire_format(AperClient::<S>::new(), wire_format, move |message| {
+            let _ = outbound_sender.send(message);
+        });
+
+    if ready.send(Ok(conn.store())).is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            Some(message) = outbound.recv() => {
+                let message = match message.encode_frame(wire_format) {
+                    Ok(Frame::Text(text)) => Message::Text(text),
+                    Ok(Frame::Binary(bytes)) => Message::Binary(bytes),
+                    Err(err) => {
+                        tracing::error!(?err, "failed to encode message; dropping it");
+                        continue;
+                    }
+                };
+                if let Err(err) = sink.send(message).await {
+                    tracing::warn!(?err, "failed to send message; closing connection");
+                    break;
+                }
+            }
+            message = stream.next() => {
+                let frame = match message {
+                    Some(Ok(Message::Binary(bytes))) => Frame::Binary(bytes),
+                    Some(Ok(Message::Text(text))) => Frame::Text(text),
+                    Some(Ok(Message::Close(_))) | None => {
+                        tracing::info!("connection closed");
+                        break;
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(err)) => {
+                        tracing::warn!(?err, "connection failed");
+                        break;
+                    }
+                };
+
+                let message = match MessageToClient::decode_frame(&frame, wire_format) {
+                    Ok(message) => message,
+                    Err(err) => {
+                        tracing::warn!(?err, "ignoring malformed message");
+                        continue;
+                    }
+                };
+
+                conn.receive(&message);
+                *client_id.lock().unwrap() = conn.client_id();
+            }
+            command = commands.recv() => {
+                let Some(command) = command else {
+                    // Every clone of the client has been dropped.
+                    break;
+                };
+
+                match command {
+                    Command::Apply(intent, reply) => {
+                        let _ = reply.send(conn.apply(intent));
+                    }
+                    Command::Read(read) => read(&conn.state()),
+                }
+            }
+        }
+    }
+}
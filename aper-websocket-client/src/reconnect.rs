@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+/// Controls how an [`AperWebSocketClient`](crate::AperWebSocketClient) reconnects after
+/// its socket closes.
+///
+/// The delay before each attempt grows exponentially from `initial_delay` up to
+/// `max_delay`. Each delay is then shortened by a random fraction of up to `jitter`, so
+/// that clients dropped at the same moment do not all reconnect at the same moment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectConfig {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// Between 0 (no jitter) and 1.
+    pub jitter: f64,
+    /// Give up after this many consecutive failed attempts. `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// A config that never reconnects.
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Self::default()
+        }
+    }
+
+    /// The delay before the given attempt (starting at 0), where `random` is uniformly
+    /// distributed in `[0, 1)`. Returns `None` once `max_attempts` is reached.
+    pub fn delay(&self, attempt: u32, random: f64) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+
+        let backoff = self.initial_delay.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        let backoff = backoff.min(self.max_delay.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0) * random;
+
+        Some(Duration::from_secs_f64(backoff * (1.0 - jitter)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delay_backs_off_up_to_max() {
+        let config = ReconnectConfig {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: Some(5),
+        };
+
+        let delays: Vec<_> = (0..6).map(|attempt| config.delay(attempt, 0.0)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(2)),
+                Some(Duration::from_secs(4)),
+                Some(Duration::from_secs(5)),
+                Some(Duration::from_secs(5)),
+                None,
+            ]
+        );
+
+        assert_eq!(config.delay(1, 0.5), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn disabled_never_reconnects() {
+        assert_eq!(ReconnectConfig::disabled().delay(0, 0.0), None);
+    }
+}
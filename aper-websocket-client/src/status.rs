@@ -0,0 +1,43 @@
+use std::cell::RefCell;
+
+/// The state of an [`AperWebSocketClient`](crate::AperWebSocketClient)'s connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// The first connection is being opened.
+    Connecting,
+    Open,
+    /// The connection dropped, and reconnection attempt `attempt` (starting at 1) is
+    /// waiting or in progress.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// The connection dropped and will not be reopened.
+    Closed,
+}
+
+/// Listeners registered with [`AperWebSocketClient::listen`](crate::AperWebSocketClient::listen).
+#[derive(Default)]
+pub(crate) struct StatusListeners {
+    listeners: RefCell<Vec<Box<dyn Fn() -> bool>>>,
+}
+
+impl StatusListeners {
+    pub fn listen<F: Fn() -> bool + 'static>(&self, listener: F) {
+        self.listeners.borrow_mut().push(Box::new(listener));
+    }
+
+    /// Calls every listener, dropping those that return `false`.
+    pub fn alert(&self) {
+        // Listeners are taken out while they run, so that they can register new listeners
+        // or cause another alert.
+        let listeners = std::mem::take(&mut *self.listeners.borrow_mut());
+        let kept: Vec<_> = listeners
+            .into_iter()
+            .filter(|listener| listener())
+            .collect();
+
+        let mut listeners = self.listeners.borrow_mut();
+        let added = std::mem::replace(&mut *listeners, kept);
+        listeners.extend(added);
+    }
+}
@@ -1,42 +1,137 @@
-use crate::websocket::{Message, WebSocketConnection};
+use crate::websocket::{Message, WebSocketConnection, WebSocketSender};
 use anyhow::Result;
-use serde::{de::DeserializeOwned, Serialize};
-use std::marker::PhantomData;
+use aper::codec::{Frame, FramedMessage, WireFormat};
+use std::{
+    collections::VecDeque,
+    marker::PhantomData,
+    sync::{Arc, Mutex, MutexGuard},
+};
 
-pub struct TypedWebsocketConnection<Inbound: DeserializeOwned, Outbound: Serialize, F>
+/// Messages waiting to be sent, oldest first.
+///
+/// An outbox is shared rather than owned by a connection, so messages queued while one
+/// socket is down can be sent on the next.
+pub struct Outbox<T> {
+    queue: Arc<Mutex<VecDeque<T>>>,
+}
+
+impl<T> Clone for Outbox<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> Default for Outbox<T> {
+    fn default() -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+}
+
+impl<T> Outbox<T> {
+    pub fn lock(&self) -> MutexGuard<'_, VecDeque<T>> {
+        self.queue.lock().unwrap()
+    }
+}
+
+impl<T: FramedMessage> Outbox<T> {
+    /// Sends queued messages in order, stopping at the first one that fails to send.
+    fn flush(&self, sender: &WebSocketSender, wire_format: WireFormat) {
+        let mut queue = self.lock();
+
+        while let Some(message) = queue.front() {
+            let message = match message.encode_frame(wire_format) {
+                Ok(Frame::Text(text)) => Message::Text(text),
+                Ok(Frame::Binary(bytes)) => Message::Bytes(bytes),
+                Err(err) => {
+                    tracing::error!(?err, "failed to encode message; dropping it");
+                    queue.pop_front();
+                    continue;
+                }
+            };
+
+            if let Err(err) = sender.send(&message) {
+                tracing::warn!(?err, "failed to send message; will retry when reconnected");
+                break;
+            }
+
+            queue.pop_front();
+        }
+    }
+}
+
+pub struct TypedWebsocketConnection<Inbound: FramedMessage, Outbound: FramedMessage, F>
 where
     F: Fn(Inbound) + 'static,
 {
     _ph: PhantomData<(Inbound, Outbound, F)>,
     conn: WebSocketConnection<Box<dyn Fn(Message)>>,
+    outbox: Outbox<Outbound>,
+    wire_format: WireFormat,
 }
 
-impl<Inbound: DeserializeOwned, Outbound: Serialize, F>
+impl<Inbound: FramedMessage, Outbound: FramedMessage + Clone + 'static, F>
     TypedWebsocketConnection<Inbound, Outbound, F>
 where
     F: Fn(Inbound) + 'static,
 {
-    pub fn new(url: &str, callback: F) -> Result<Self> {
-        let f: Box<dyn Fn(Message)> = Box::new(move |m: Message| match m {
-            Message::Text(txt) => {
-                let result: Inbound = serde_json::from_str(&txt).unwrap();
-                callback(result);
-            }
-            Message::Bytes(bytes) => {
-                let result: Inbound = bincode::deserialize(&bytes).unwrap();
-                callback(result);
+    /// Opens a connection that sends messages through `outbox`, encoded in
+    /// `wire_format`. Messages already in the outbox are sent once the socket opens,
+    /// after which `on_open` is called.
+    pub fn new<G, H>(
+        url: &str,
+        callback: F,
+        outbox: Outbox<Outbound>,
+        wire_format: WireFormat,
+        on_open: G,
+        on_close: H,
+    ) -> Result<Self>
+    where
+        G: Fn() + 'static,
+        H: Fn() + 'static,
+    {
+        let f: Box<dyn Fn(Message)> = Box::new(move |m: Message| {
+            let frame = match m {
+                Message::Text(txt) => Frame::Text(txt),
+                Message::Bytes(bytes) => Frame::Binary(bytes),
+            };
+
+            match Inbound::decode_frame(&frame, wire_format) {
+                Ok(result) => callback(result),
+                Err(err) => tracing::warn!(?err, "ignoring malformed message"),
             }
         });
-        let conn = WebSocketConnection::new(url, f)?;
+
+        let outbox_ = outbox.clone();
+        let conn = WebSocketConnection::new(
+            url,
+            f,
+            move |sender: &WebSocketSender| {
+                outbox_.flush(sender, wire_format);
+                on_open();
+            },
+            on_close,
+        )?;
 
         Ok(TypedWebsocketConnection {
             conn,
+            outbox,
+            wire_format,
             _ph: PhantomData,
         })
     }
 
+    /// Sends a message, or queues it in the outbox if the socket is not open. Messages are
+    /// always sent in the order they are passed to this method.
     pub fn send(&self, message: &Outbound) {
-        let message = Message::Bytes(bincode::serialize(message).unwrap());
-        self.conn.send(&message);
+        self.outbox.lock().push_back(message.clone());
+
+        let sender = self.conn.sender();
+        if sender.is_open() {
+            self.outbox.flush(sender, self.wire_format);
+        }
     }
 }
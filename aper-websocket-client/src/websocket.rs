@@ -1,20 +1,39 @@
 use anyhow::{anyhow, Result};
-use std::sync::Arc;
-use std::{marker::PhantomData, sync::Mutex};
+use std::marker::PhantomData;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::{prelude::Closure, JsValue};
 use web_sys::{MessageEvent, WebSocket};
 
+/// A handle for sending messages on a [`WebSocketConnection`]'s socket.
+#[derive(Clone)]
+pub struct WebSocketSender {
+    socket: WebSocket,
+}
+
+impl WebSocketSender {
+    pub fn is_open(&self) -> bool {
+        self.socket.ready_state() == WebSocket::OPEN
+    }
+
+    pub fn send(&self, message: &Message) -> Result<()> {
+        match message {
+            Message::Text(txt) => self.socket.send_with_str(txt),
+            Message::Bytes(bytes) => self.socket.send_with_u8_array(bytes),
+        }
+        .map_err(|err| anyhow!("Error sending message. {:?}", err))
+    }
+}
+
 pub struct WebSocketConnection<F>
 where
     F: Fn(Message) + 'static,
 {
-    socket: WebSocket,
+    sender: WebSocketSender,
     _message_handler: Closure<dyn FnMut(MessageEvent)>,
     _conn_handler: Closure<dyn FnMut(JsValue)>,
+    _close_handler: Closure<dyn FnMut(JsValue)>,
+    _error_handler: Closure<dyn FnMut(JsValue)>,
     _ph: PhantomData<F>,
-
-    pending: Arc<Mutex<Option<Message>>>,
 }
 
 #[derive(Clone)]
@@ -27,7 +46,14 @@ impl<F> WebSocketConnection<F>
 where
     F: Fn(Message) + 'static,
 {
-    pub fn new(url: &str, callback: F) -> Result<Self> {
+    /// Opens a socket to `url`. `on_open` is called once the socket is open, so that
+    /// messages queued in the meantime can be sent. `on_close` is called when the socket
+    /// closes or fails to open, but not when the connection is dropped.
+    pub fn new<G, H>(url: &str, callback: F, on_open: G, on_close: H) -> Result<Self>
+    where
+        G: Fn(&WebSocketSender) + 'static,
+        H: Fn() + 'static,
+    {
         let ws =
             WebSocket::new(url).map_err(|err| anyhow!("Error creating websocket. {:?}", err))?;
         ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
@@ -49,49 +75,53 @@ where
 
         ws.set_onmessage(Some(message_handler.as_ref().unchecked_ref()));
 
-        let pending = Arc::new(Mutex::new(None));
-        let pending_ = pending.clone();
-        let ws_ = ws.clone();
+        let sender = WebSocketSender { socket: ws.clone() };
+        let sender_ = sender.clone();
         let conn_handler = Closure::<dyn FnMut(JsValue)>::wrap(Box::new(move |_: JsValue| {
-            let mut pending = pending_.lock().unwrap();
-            if let Some(message) = pending.take() {
-                match message {
-                    Message::Text(txt) => {
-                        ws_.send_with_str(&txt).unwrap();
-                    }
-                    Message::Bytes(bytes) => {
-                        ws_.send_with_u8_array(&bytes).unwrap();
-                    }
-                }
-            }
+            on_open(&sender_);
         }));
 
         ws.set_onopen(Some(conn_handler.as_ref().unchecked_ref()));
 
+        let close_handler = Closure::<dyn FnMut(JsValue)>::wrap(Box::new(move |_: JsValue| {
+            on_close();
+        }));
+
+        ws.set_onclose(Some(close_handler.as_ref().unchecked_ref()));
+
+        // An error event is always followed by a close event, which is where it is handled.
+        let error_handler = Closure::<dyn FnMut(JsValue)>::wrap(Box::new(move |e: JsValue| {
+            tracing::warn!(?e, "websocket error");
+        }));
+
+        ws.set_onerror(Some(error_handler.as_ref().unchecked_ref()));
+
         Ok(WebSocketConnection {
-            socket: ws,
+            sender,
             _message_handler: message_handler,
             _conn_handler: conn_handler,
+            _close_handler: close_handler,
+            _error_handler: error_handler,
             _ph: PhantomData,
-            pending,
         })
     }
 
-    pub fn send(&self, message: &Message) {
-        // if the socket is not open, queue the message
-        if self.socket.ready_state() != WebSocket::OPEN {
-            let mut pending = self.pending.lock().unwrap();
-            *pending = Some(message.clone());
-            return;
-        }
+    pub fn sender(&self) -> &WebSocketSender {
+        &self.sender
+    }
+}
 
-        match message {
-            Message::Text(txt) => {
-                self.socket.send_with_str(txt).unwrap();
-            }
-            Message::Bytes(bytes) => {
-                self.socket.send_with_u8_array(bytes).unwrap();
-            }
-        }
+impl<F> Drop for WebSocketConnection<F>
+where
+    F: Fn(Message) + 'static,
+{
+    fn drop(&mut self) {
+        // The handlers are about to be dropped, so detach them before closing the socket.
+        let socket = &self.sender.socket;
+        socket.set_onmessage(None);
+        socket.set_onopen(None);
+        socket.set_onclose(None);
+        socket.set_onerror(None);
+        let _ = socket.close();
     }
 }
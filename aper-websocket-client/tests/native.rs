@@ -0,0 +1,136 @@
+#![cfg(feature = "native")]
+
+use aper::{
+    codec::{Frame, WireFormat},
+    connection::ServerConnection,
+    data_structures::Atom,
+    Aper, AperSync, IntentMetadata,
+};
+use aper_websocket_client::native::{AperWebSocketClient, ApplyError};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message;
+
+#[derive(AperSync, Clone)]
+struct Counter {
+    value: Atom<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct Increment;
+
+impl Aper for Counter {
+    type Intent = Increment;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, _intent: &Increment, _metadata: &IntentMetadata) -> Result<(), ()> {
+        self.value.set(self.value.get() + 1);
+        Ok(())
+    }
+}
+
+/// Serves a `ServerConnection` over websockets on a local port, returning its URL.
+async fn serve() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let server = Arc::new(Mutex::new(ServerConnection::<Counter>::new()));
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let server = server.clone();
+
+            tokio::spawn(async move {
+                let socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+                let (mut sink, mut stream) = socket.split();
+
+                let (sender, mut outbound) = mpsc::unbounded_channel();
+                let mut handle = server.lock().unwrap().connect_framed(move |frame| {
+                    let _ = sender.send(frame);
+                });
+
+                tokio::spawn(async move {
+                    while let Some(frame) = outbound.recv().await {
+                        let message = match frame {
+                            Frame::Text(text) => Message::Text(text),
+                            Frame::Binary(bytes) => Message::Binary(bytes),
+                        };
+                        if sink.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                while let Some(Ok(message)) = stream.next().await {
+                    match message {
+                        Message::Text(text) => handle.receive_frame(&Frame::Text(text)).unwrap(),
+                        Message::Binary(bytes) => {
+                            handle.receive_frame(&Frame::Binary(bytes)).unwrap()
+                        }
+                        _ => {}
+                    }
+                }
+            });
+        }
+    });
+
+    url
+}
+
+async fn wait_for(condition: impl Fn() -> bool) {
+    for _ in 0..100 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    panic!("Timed out waiting for condition.");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_sync_through_server() {
+    let url = serve().await;
+
+    let alice = AperWebSocketClient::<Counter>::new(&url).await.unwrap();
+    let bob = AperWebSocketClient::<Counter>::with_wire_format(&url, WireFormat::Json)
+        .await
+        .unwrap();
+
+    wait_for(|| alice.client_id().is_some() && bob.client_id().is_some()).await;
+    assert_ne!(alice.client_id(), bob.client_id());
+
+    alice.apply(Increment).await.unwrap();
+    assert_eq!(alice.read(|state| state.value.get()).await, 1);
+
+    bob.apply(Increment).await.unwrap();
+
+    wait_for(|| alice.state().value.get() == 2 && bob.state().value.get() == 2).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn apply_fails_once_server_closes_socket() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+        socket.close(None).await.unwrap();
+    });
+
+    let client = AperWebSocketClient::<Counter>::new(&url).await.unwrap();
+    wait_for(|| !client.is_connected()).await;
+
+    assert!(matches!(
+        client.apply(Increment).await,
+        Err(ApplyError::Disconnected)
+    ));
+    assert_eq!(client.read(|state| state.value.get()).await, 0);
+}
@@ -1,97 +1,445 @@
 use proc_macro2::Ident;
 use proc_macro2::Literal;
+use proc_macro2::Span;
 use proc_macro2::TokenStream;
-use quote::quote;
-use std::collections::BTreeSet;
+use quote::{quote, quote_spanned};
+use std::collections::{BTreeMap, BTreeSet};
+use syn::spanned::Spanned;
 
-#[proc_macro_derive(AperSync)]
+#[proc_macro_derive(AperSync, attributes(aper))]
 pub fn attach_derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let state = MacroState::from_tokens(input.into());
-    let result = state.generate_impl();
+    let result = match MacroState::from_tokens(input.into()) {
+        Ok(state) => state.generate_impl(),
+        Err(err) => err.to_compile_error(),
+    };
     result.into()
 }
 
-enum StructType {
-    Record(BTreeSet<String>),
-    Tuple(usize),
-    Unit,
+/// Returns an error if two entries share a key. `entries` are `(key, span)` pairs; the
+/// error points at the second occurrence.
+fn check_unique_keys<'a>(
+    entries: impl Iterator<Item = (&'a [u8], Span)>,
+    what: &str,
+) -> syn::Result<()> {
+    let mut seen = BTreeMap::new();
+
+    for (key, span) in entries {
+        if seen.insert(key, span).is_some() {
+            let key = match std::str::from_utf8(key) {
+                Ok(key) => key.to_string(),
+                Err(_) => Literal::byte_string(key).to_string(),
+            };
+            return Err(syn::Error::new(
+                span,
+                format!("duplicate AperSync {} key `{}`", what, key),
+            ));
+        }
+    }
+
+    Ok(())
 }
 
-struct MacroState {
-    name: Ident,
-    fields: StructType,
+/// Options given in `#[aper(...)]` attributes, along with the span of each option.
+#[derive(Default)]
+struct Attributes {
+    /// Store the field (or variant) under this key instead of its Rust name.
+    key: Option<(Vec<u8>, Span)>,
+    /// Do not store the field; initialize it with `Default::default()` instead.
+    skip: Option<Span>,
 }
 
-impl MacroState {
-    fn from_tokens(tokens: TokenStream) -> Self {
-        let ast = syn::parse2::<syn::ItemStruct>(tokens.clone()).unwrap();
-        let name = ast.ident;
-        let fields = match ast.fields {
-            syn::Fields::Named(fields) => {
-                let fields = fields
-                    .named
-                    .iter()
-                    .map(|field| {
-                        let name = field.ident.as_ref().unwrap().to_string();
-                        name
-                    })
-                    .collect();
-                StructType::Record(fields)
-            }
-            syn::Fields::Unnamed(fields) => {
-                let fields = fields.unnamed.len();
-                StructType::Tuple(fields)
+impl Attributes {
+    fn from_attrs(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut result = Attributes::default();
+
+        for attr in attrs {
+            if !attr.path().is_ident("aper") {
+                continue;
             }
+
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let value: syn::LitStr = meta.value()?.parse()?;
+                    result.key = Some((value.value().into_bytes(), value.span()));
+                } else if meta.path.is_ident("key") {
+                    let value: syn::LitByteStr = meta.value()?.parse()?;
+                    result.key = Some((value.value(), value.span()));
+                } else if meta.path.is_ident("skip") {
+                    result.skip = Some(meta.path.span());
+                } else {
+                    return Err(meta
+                        .error("unsupported aper attribute; expected `rename`, `key`, or `skip`"));
+                }
+
+                Ok(())
+            })?;
+        }
+
+        Ok(result)
+    }
+}
+
+struct Field {
+    /// The field's name, or `None` for tuple fields.
+    ident: Option<Ident>,
+    ty: syn::Type,
+    /// The key of the child prefix that the field is attached to.
+    key: Vec<u8>,
+    /// Where the key came from, for error messages.
+    key_span: Span,
+    skip: bool,
+}
+
+impl Field {
+    fn from_field(index: usize, field: &syn::Field) -> syn::Result<Self> {
+        let attrs = Attributes::from_attrs(&field.attrs)?;
+        let (key, key_span) = attrs.key.unwrap_or_else(|| match &field.ident {
+            Some(ident) => (ident.to_string().into_bytes(), ident.span()),
+            None => (index.to_be_bytes().to_vec(), field.ty.span()),
+        });
+
+        Ok(Field {
+            ident: field.ident.clone(),
+            ty: field.ty.clone(),
+            key,
+            key_span,
+            skip: attrs.skip.is_some(),
+        })
+    }
+
+    fn generate_value(&self) -> TokenStream {
+        if self.skip {
+            return quote! { ::std::default::Default::default() };
+        }
+
+        // Spanning the call to the field's type makes the compiler point at the field if
+        // its type does not implement `AperSync`.
+        let key = Literal::byte_string(&self.key);
+        quote_spanned! {self.ty.span()=>
+            aper::AperSync::attach(store.child(
+                aper::Bytes::from_static(#key)
+            ))
+        }
+    }
+}
+
+enum StructType {
+    Record(Vec<Field>),
+    Tuple(Vec<Field>),
+    Unit,
+}
+
+impl StructType {
+    fn from_fields(fields: &syn::Fields) -> syn::Result<Self> {
+        let specs = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| Field::from_field(i, field))
+            .collect::<syn::Result<Vec<_>>>()?;
+
+        check_unique_keys(
+            specs
+                .iter()
+                .filter(|field| !field.skip)
+                .map(|field| (field.key.as_slice(), field.key_span)),
+            "field",
+        )?;
+
+        Ok(match fields {
+            syn::Fields::Named(_) => StructType::Record(specs),
+            syn::Fields::Unnamed(_) => StructType::Tuple(specs),
             syn::Fields::Unit => StructType::Unit,
-        };
-        Self { name, fields }
+        })
     }
 
-    fn generate_impl(&self) -> TokenStream {
-        let name = &self.name;
-        let fields = match &self.fields {
+    fn fields(&self) -> &[Field] {
+        match self {
+            StructType::Record(fields) | StructType::Tuple(fields) => fields,
+            StructType::Unit => &[],
+        }
+    }
+
+    /// Generates an expression that constructs `path` (a struct or enum variant) with each
+    /// field attached to a child of `store`.
+    fn generate_constructor(&self, path: TokenStream) -> TokenStream {
+        match self {
             StructType::Record(fields) => {
                 let fields = fields.iter().map(|field| {
-                    let field = syn::Ident::new(field, proc_macro2::Span::call_site());
-                    let name = Literal::byte_string(field.to_string().as_bytes());
+                    let ident = &field.ident;
+                    let value = field.generate_value();
                     quote! {
-                        #field: aper::AperSync::attach(store.child(
-                            aper::Bytes::from_static(#name)
-                        ))
+                        #ident: #value
                     }
                 });
                 quote! {
-                    #name {
+                    #path {
                         #(#fields),*
                     }
                 }
             }
             StructType::Tuple(fields) => {
-                let fields = (0..*fields).map(|i| {
-                    let i = Literal::byte_string(i.to_be_bytes().as_slice());
-                    quote! {
-                        aper::AperSync::attach(store.child(
-                            aper::Bytes::from_static(#i)
-                        ))
-                    }
-                });
+                let fields = fields.iter().map(Field::generate_value);
                 quote! {
-                    #name(#(#fields),*)
+                    #path(#(#fields),*)
                 }
             }
             StructType::Unit => quote! {
-                #name
+                #path
             },
+        }
+    }
+
+    /// Like `generate_constructor`, but attaches the fields under a child of `store` named
+    /// `key`, so that each variant of an enum has its own subtree.
+    fn generate_variant_constructor(&self, key: &Literal, path: TokenStream) -> TokenStream {
+        if self.fields().iter().all(|field| field.skip) {
+            return self.generate_constructor(path);
+        }
+
+        let constructor = self.generate_constructor(path);
+        quote! {
+            {
+                let mut store = store.child(aper::Bytes::from_static(#key));
+                #constructor
+            }
+        }
+    }
+
+    /// Generates a pattern that matches `path` regardless of its fields.
+    fn generate_wildcard_pattern(&self, path: TokenStream) -> TokenStream {
+        match self {
+            StructType::Record(_) => quote! { #path { .. } },
+            StructType::Tuple(_) => quote! { #path(..) },
+            StructType::Unit => quote! { #path },
+        }
+    }
+}
+
+struct Variant {
+    ident: Ident,
+    /// The name the variant is stored under. Also used as its name in `AperSyncEnum`.
+    name: String,
+    /// Where the name came from, for error messages.
+    name_span: Span,
+    fields: StructType,
+}
+
+impl Variant {
+    fn from_variant(variant: &syn::Variant) -> syn::Result<Self> {
+        let attrs = Attributes::from_attrs(&variant.attrs)?;
+
+        if let Some(span) = attrs.skip {
+            return Err(syn::Error::new(span, "enum variants cannot be skipped"));
+        }
+
+        let (name, name_span) = match attrs.key {
+            Some((key, span)) => {
+                let name = String::from_utf8(key)
+                    .map_err(|_| syn::Error::new(span, "enum variant keys must be valid UTF-8"))?;
+                (name, span)
+            }
+            None => (variant.ident.to_string(), variant.ident.span()),
         };
 
+        Ok(Variant {
+            ident: variant.ident.clone(),
+            name,
+            name_span,
+            fields: StructType::from_fields(&variant.fields)?,
+        })
+    }
+}
+
+enum ItemType {
+    Struct(StructType),
+    Enum(Vec<Variant>),
+}
+
+struct MacroState {
+    name: Ident,
+    generics: syn::Generics,
+    item: ItemType,
+}
+
+impl MacroState {
+    fn from_tokens(tokens: TokenStream) -> syn::Result<Self> {
+        let ast = syn::parse2::<syn::DeriveInput>(tokens)?;
+        let item = match &ast.data {
+            syn::Data::Struct(data) => ItemType::Struct(StructType::from_fields(&data.fields)?),
+            syn::Data::Enum(data) => {
+                if data.variants.is_empty() {
+                    return Err(syn::Error::new_spanned(
+                        &ast.ident,
+                        "AperSync cannot be derived for enums without variants",
+                    ));
+                }
+
+                let variants = data
+                    .variants
+                    .iter()
+                    .map(Variant::from_variant)
+                    .collect::<syn::Result<Vec<_>>>()?;
+
+                check_unique_keys(
+                    variants
+                        .iter()
+                        .map(|variant| (variant.name.as_bytes(), variant.name_span)),
+                    "variant",
+                )?;
+
+                ItemType::Enum(variants)
+            }
+            syn::Data::Union(data) => {
+                return Err(syn::Error::new_spanned(
+                    data.union_token,
+                    "AperSync can only be derived for structs and enums",
+                ));
+            }
+        };
+
+        Ok(Self {
+            name: ast.ident,
+            generics: ast.generics,
+            item,
+        })
+    }
+
+    /// Every field of the item (of every variant, for enums).
+    fn fields(&self) -> Vec<&Field> {
+        match &self.item {
+            ItemType::Struct(fields) => fields.fields().iter().collect(),
+            ItemType::Enum(variants) => variants
+                .iter()
+                .flat_map(|variant| variant.fields.fields())
+                .collect(),
+        }
+    }
+
+    fn generate_impl(&self) -> TokenStream {
+        match &self.item {
+            ItemType::Struct(fields) => self.generate_struct_impl(fields),
+            ItemType::Enum(variants) => self.generate_enum_impl(variants),
+        }
+    }
+
+    /// For generic items, every field type is required to implement `AperSync` (or
+    /// `Default`, for skipped fields). The item itself is also required to implement
+    /// `Clone` (a supertrait of `AperSync`), since a derived `Clone` impl may add bounds of
+    /// its own to the type parameters.
+    fn where_clause(&self) -> Option<syn::WhereClause> {
+        if self.generics.params.is_empty() {
+            return self.generics.where_clause.clone();
+        }
+
+        let mut where_clause =
+            self.generics
+                .where_clause
+                .clone()
+                .unwrap_or_else(|| syn::WhereClause {
+                    where_token: Default::default(),
+                    predicates: Default::default(),
+                });
+
+        let mut seen = BTreeSet::new();
+        for field in self.fields() {
+            let ty = &field.ty;
+            let predicate: syn::WherePredicate = if field.skip {
+                syn::parse_quote! { #ty: ::std::default::Default }
+            } else {
+                syn::parse_quote! { #ty: aper::AperSync }
+            };
+
+            if seen.insert(quote! { #predicate }.to_string()) {
+                where_clause.predicates.push(predicate);
+            }
+        }
+
+        let name = &self.name;
+        let (_, ty_generics, _) = self.generics.split_for_impl();
+        where_clause
+            .predicates
+            .push(syn::parse_quote! { #name #ty_generics: ::std::clone::Clone });
+
+        Some(where_clause)
+    }
+
+    fn generate_struct_impl(&self, fields: &StructType) -> TokenStream {
+        let name = &self.name;
+        let (impl_generics, ty_generics, _) = self.generics.split_for_impl();
+        let where_clause = self.where_clause();
+        let fields = fields.generate_constructor(quote! { #name });
+
         quote! {
-            impl aper::AperSync for #name {
+            impl #impl_generics aper::AperSync for #name #ty_generics #where_clause {
                 fn attach(mut store: aper::StoreHandle) -> Self {
                     #fields
                 }
             }
         }
     }
+
+    /// The active variant's name is stored under the empty key of the enum's own prefix,
+    /// and the variant's fields are attached under a child prefix named after the variant.
+    /// If no variant has been stored yet, the first variant is used.
+    fn generate_enum_impl(&self, variants: &[Variant]) -> TokenStream {
+        let name = &self.name;
+        let (impl_generics, ty_generics, _) = self.generics.split_for_impl();
+        let where_clause = self.where_clause();
+
+        let attach_arms = variants.iter().map(|variant| {
+            let ident = &variant.ident;
+            let key = Literal::byte_string(variant.name.as_bytes());
+            let constructor = variant
+                .fields
+                .generate_variant_constructor(&key, quote! { #name::#ident });
+            quote! {
+                Some(#key) => #constructor,
+            }
+        });
+
+        let default_variant = &variants[0];
+        let default_ident = &default_variant.ident;
+        let default_key = Literal::byte_string(default_variant.name.as_bytes());
+        let default_constructor = default_variant
+            .fields
+            .generate_variant_constructor(&default_key, quote! { #name::#default_ident });
+
+        let variant_arms = variants.iter().map(|variant| {
+            let ident = &variant.ident;
+            let pattern = variant
+                .fields
+                .generate_wildcard_pattern(quote! { #name::#ident });
+            let variant_name = &variant.name;
+            quote! {
+                #pattern => #variant_name
+            }
+        });
+
+        let variant_names = variants.iter().map(|variant| &variant.name);
+
+        quote! {
+            impl #impl_generics aper::AperSync for #name #ty_generics #where_clause {
+                fn attach(mut store: aper::StoreHandle) -> Self {
+                    match store.get(&aper::Bytes::new()).as_deref() {
+                        #(#attach_arms)*
+                        _ => #default_constructor,
+                    }
+                }
+            }
+
+            impl #impl_generics aper::AperSyncEnum for #name #ty_generics #where_clause {
+                fn variant(&self) -> &'static str {
+                    match self {
+                        #(#variant_arms),*
+                    }
+                }
+
+                fn variants() -> &'static [&'static str] {
+                    &[#(#variant_names),*]
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -104,7 +452,7 @@ mod tests {
             struct MyStruct;
         };
 
-        let state = MacroState::from_tokens(input);
+        let state = MacroState::from_tokens(input).unwrap();
         let result = state.generate_impl();
 
         let expected = quote! {
@@ -127,7 +475,7 @@ mod tests {
             }
         };
 
-        let state = MacroState::from_tokens(input);
+        let state = MacroState::from_tokens(input).unwrap();
         let result = state.generate_impl();
 
         let expected = quote! {
@@ -150,7 +498,7 @@ mod tests {
             struct MyStruct(i32, String);
         };
 
-        let state = MacroState::from_tokens(input);
+        let state = MacroState::from_tokens(input).unwrap();
         let result = state.generate_impl();
 
         let expected = quote! {
@@ -166,4 +514,118 @@ mod tests {
 
         assert_eq!(result.to_string(), expected.to_string());
     }
+
+    #[test]
+    fn test_generate_impl_for_enum() {
+        let input = quote! {
+            enum MyEnum {
+                A,
+                B(i32),
+                C { x: i32 },
+            }
+        };
+
+        let state = MacroState::from_tokens(input).unwrap();
+        let result = state.generate_impl();
+
+        let expected = quote! {
+            impl aper::AperSync for MyEnum {
+                fn attach(mut store: aper::StoreHandle) -> Self {
+                    match store.get(&aper::Bytes::new()).as_deref() {
+                        Some(b"A") => MyEnum::A,
+                        Some(b"B") => {
+                            let mut store = store.child(aper::Bytes::from_static(b"B"));
+                            MyEnum::B(aper::AperSync::attach(store.child(aper::Bytes::from_static(b"\0\0\0\0\0\0\0\0"))))
+                        },
+                        Some(b"C") => {
+                            let mut store = store.child(aper::Bytes::from_static(b"C"));
+                            MyEnum::C {
+                                x: aper::AperSync::attach(store.child(aper::Bytes::from_static(b"x")))
+                            }
+                        },
+                        _ => MyEnum::A,
+                    }
+                }
+            }
+
+            impl aper::AperSyncEnum for MyEnum {
+                fn variant(&self) -> &'static str {
+                    match self {
+                        MyEnum::A => "A",
+                        MyEnum::B(..) => "B",
+                        MyEnum::C { .. } => "C"
+                    }
+                }
+
+                fn variants() -> &'static [&'static str] {
+                    &["A", "B", "C"]
+                }
+            }
+        };
+
+        assert_eq!(result.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn test_generate_impl_for_generic_struct() {
+        let input = quote! {
+            struct MyStruct<'a, T, const N: u32> where T: Clone {
+                field1: Atom<T>,
+                field2: FixedArray<N, &'a str>,
+            }
+        };
+
+        let state = MacroState::from_tokens(input).unwrap();
+        let result = state.generate_impl();
+
+        let expected = quote! {
+            impl<'a, T, const N: u32> aper::AperSync for MyStruct<'a, T, N>
+            where
+                T: Clone,
+                Atom<T>: aper::AperSync,
+                FixedArray<N, &'a str>: aper::AperSync,
+                MyStruct<'a, T, N>: ::std::clone::Clone
+            {
+                fn attach(mut store: aper::StoreHandle) -> Self {
+                    MyStruct {
+                        field1: aper::AperSync::attach(store.child(aper::Bytes::from_static(b"field1"))),
+                        field2: aper::AperSync::attach(store.child(aper::Bytes::from_static(b"field2")))
+                    }
+                }
+            }
+        };
+
+        assert_eq!(result.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn test_generate_impl_with_field_attributes() {
+        let input = quote! {
+            struct MyStruct {
+                #[aper(rename = "old_name")]
+                field1: Atom<i32>,
+                #[aper(key = b"\x01")]
+                field2: Atom<i32>,
+                #[aper(skip)]
+                field3: Vec<u8>,
+            }
+        };
+
+        let state = MacroState::from_tokens(input).unwrap();
+        let result = state.generate_impl();
+
+        let expected = quote! {
+            impl aper::AperSync for MyStruct {
+                fn attach(mut store: aper::StoreHandle) -> Self {
+                    MyStruct {
+                        field1: aper::AperSync::attach(store.child(aper::Bytes::from_static(b"old_name"))),
+                        field2: aper::AperSync::attach(store.child(aper::Bytes::from_static(b"\x01"))),
+                        field3: ::std::default::Default::default()
+                    }
+                }
+            }
+        };
+
+        assert_eq!(result.to_string(), expected.to_string());
+    }
 }
@@ -1,11 +1,20 @@
 use crate::{
-    connection::{ClientConnection, MessageToServer},
+    connection::{ClientConnection, ClientInfo, MessageToServer},
+    intent_log::{IntentLog, LoggedIntent},
     store::{Store, StoreHandle},
-    IntentMetadata, Mutation,
+    ClientIdentity, Denied, IntentMetadata, Mutation,
 };
 use serde::{Deserialize, Serialize};
-use std::{collections::VecDeque, fmt::Debug};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{Debug, Display},
+};
 
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` does not implement `AperSync`",
+    label = "fields of an `AperSync` type must implement `AperSync`",
+    note = "to store a plain value, wrap it in `aper::data_structures::Atom`"
+)]
 pub trait AperSync: Clone {
     fn attach(map: StoreHandle) -> Self;
 
@@ -14,19 +23,82 @@ pub trait AperSync: Clone {
     }
 }
 
+/// An `AperSync` enum, whose active variant is stored alongside its fields.
+///
+/// This is implemented by `#[derive(AperSync)]` on enums. Use
+/// [`Switch`](crate::data_structures::Switch) to change the active variant.
+pub trait AperSyncEnum: AperSync {
+    /// The name of the active variant.
+    fn variant(&self) -> &'static str;
+
+    /// The names of all variants, in declaration order.
+    fn variants() -> &'static [&'static str];
+}
+
 pub trait Aper: AperSync + 'static {
     type Intent: Clone + Serialize + for<'de> Deserialize<'de> + PartialEq;
     type Error: Debug;
 
+    /// The version of the schema of the state and its intents. The server rejects clients
+    /// whose schema version differs from its own, rather than failing to decode their
+    /// intents.
+    ///
+    /// Bump it with each change that clients built against the previous version could not
+    /// decode, such as adding a field or an intent variant. There is no default, since a
+    /// version shared by every app could not tell their schemas apart.
+    const SCHEMA_VERSION: u32;
+
     fn apply(
         &mut self,
         intent: &Self::Intent,
         metadata: &IntentMetadata,
     ) -> Result<(), Self::Error>;
 
+    /// A single intent for the server to apply at the time in its metadata. Prefer
+    /// [`Aper::scheduled_events`], which allows more than one.
     fn suspended_event(&self) -> Option<(Self::Intent, IntentMetadata)> {
         None
     }
+
+    /// The intents the server should apply in the future, keyed by a name that is unique
+    /// among them, each with the time to apply it in its metadata. The server re-reads
+    /// these after every change to the state, so an event is added, replaced or cancelled
+    /// by changing the state it is derived from; see
+    /// [`Scheduler`](crate::scheduler::Scheduler).
+    ///
+    /// The default returns [`Aper::suspended_event`], if any.
+    fn scheduled_events(&self) -> BTreeMap<String, (Self::Intent, IntentMetadata)> {
+        self.suspended_event()
+            .map(|event| ("suspended_event".to_string(), event))
+            .into_iter()
+            .collect()
+    }
+
+    /// Called on the server when a client starts a session. The returned intent, if any,
+    /// is applied to the state without a client (e.g. to add the player to a game).
+    fn client_joined(&self, _client: &ClientInfo) -> Option<Self::Intent> {
+        None
+    }
+
+    /// Called on the server when a client disconnects. The returned intent, if any, is
+    /// applied to the state without a client (e.g. to mark the player as away).
+    fn client_left(&self, _client: &ClientInfo) -> Option<Self::Intent> {
+        None
+    }
+
+    /// Checks whether the client with `identity` may apply `intent` to the current state.
+    /// Called by the server before [`Aper::apply`] for each intent received from a
+    /// client; denied intents are not applied, and the client is told why.
+    ///
+    /// The default allows every intent.
+    fn authorize(
+        &self,
+        _intent: &Self::Intent,
+        _metadata: &IntentMetadata,
+        _identity: &ClientIdentity,
+    ) -> Result<(), Denied> {
+        Ok(())
+    }
 }
 
 struct SpeculativeIntent<I> {
@@ -132,6 +204,14 @@ impl<A: Aper> AperClient<A> {
         Ok(version)
     }
 
+    /// Speculative intents that the server has not yet confirmed, oldest first, along
+    /// with their client versions.
+    pub fn speculative_intents(&self) -> impl Iterator<Item = (u64, &A::Intent)> {
+        self.intent_stack
+            .iter()
+            .map(|speculative_intent| (speculative_intent.version, &speculative_intent.intent))
+    }
+
     /// Mutate the local client state according to server-verified mutations.
     pub fn mutate(
         &mut self,
@@ -151,15 +231,42 @@ impl<A: Aper> AperClient<A> {
         self.store.push_overlay();
 
         if let Some(version) = client_version {
-            self.verified_client_version = version;
-
-            if let Some(index) = self.intent_stack.front() {
-                if index.version == version {
-                    self.intent_stack.pop_front();
-                    // happy case; no need to recompute other speculative intents
-                    return;
-                }
+            if self.intent_stack.len() == 1 && self.intent_stack[0].version == version {
+                self.verified_client_version = version;
+                self.intent_stack.pop_front();
+                // happy case; the only speculative intent was confirmed, so the verified state
+                // already matches what the client was showing.
+                return;
             }
+        }
+
+        self.rebase(client_version);
+    }
+
+    /// Replace the local verified state with a full snapshot from the server, then
+    /// re-apply any speculative intents that are not covered by `client_version`.
+    pub fn reset(
+        &mut self,
+        mutations: &[Mutation],
+        client_version: Option<u64>,
+        server_version: u64,
+    ) {
+        self.store.clear();
+        self.verified_server_version = server_version;
+
+        self.store.mutate(mutations);
+
+        // push new speculative overlay
+        self.store.push_overlay();
+
+        self.rebase(client_version);
+    }
+
+    /// Drop speculative intents confirmed by `client_version`, and re-apply the rest on
+    /// top of the verified state.
+    fn rebase(&mut self, client_version: Option<u64>) {
+        if let Some(version) = client_version {
+            self.verified_client_version = version;
 
             while let Some(index) = self.intent_stack.front() {
                 if index.version > version {
@@ -192,9 +299,49 @@ impl<A: Aper> AperClient<A> {
     }
 }
 
+/// A serializable copy of an [`AperServer`]'s state, along with the version it was taken at.
+///
+/// Restoring a server with [`AperServer::from_snapshot`] keeps its version, so clients that
+/// reconnect after a restart do not see the server version go backwards.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ServerSnapshot {
+    pub version: u64,
+    pub mutations: Vec<Mutation>,
+}
+
+/// The number of versions of mutations an [`AperServer`] retains by default, for
+/// catching clients up without sending a full snapshot.
+pub const DEFAULT_HISTORY_LENGTH: usize = 1024;
+
+/// Returned by [`AperServer::apply`] when an intent is not applied.
+#[derive(Debug)]
+pub enum IntentError<E> {
+    /// The state rejected the intent.
+    Rejected(E),
+    /// The intent could not be recorded in the server's intent log, so it was not applied.
+    NotLogged(std::io::Error),
+}
+
+impl<E: Debug> Display for IntentError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntentError::Rejected(error) => write!(f, "Intent was rejected: {:?}", error),
+            IntentError::NotLogged(error) => write!(f, "Failed to log intent: {}", error),
+        }
+    }
+}
+
+impl<E: Debug> std::error::Error for IntentError<E> {}
+
 pub struct AperServer<A: Aper> {
     map: Store,
     version: u64,
+    intent_log: Option<Box<dyn IntentLog<A::Intent>>>,
+
+    /// Mutations produced by recent versions, oldest first, as `(version, mutations)`.
+    history: VecDeque<(u64, Vec<Mutation>)>,
+    history_length: usize,
+
     _phantom: std::marker::PhantomData<A>,
 }
 
@@ -211,14 +358,76 @@ impl<A: Aper> AperServer<A> {
         Self {
             map,
             version: 0,
+            intent_log: None,
+            history: VecDeque::new(),
+            history_length: DEFAULT_HISTORY_LENGTH,
             _phantom: std::marker::PhantomData,
         }
     }
 
+    /// Restore a server from a snapshot previously returned by [`AperServer::snapshot`].
+    ///
+    /// The restored server has no history, so clients that were connected before the
+    /// snapshot was taken are caught up with a full snapshot.
+    pub fn from_snapshot(snapshot: &ServerSnapshot) -> Self {
+        let mut server = Self::new();
+        server.map.mutate(&snapshot.mutations);
+        server.version = snapshot.version;
+        server
+    }
+
+    /// Set how many versions of mutations to retain for [`AperServer::mutations_since`].
+    pub fn set_history_length(&mut self, history_length: usize) {
+        self.history_length = history_length;
+        while self.history.len() > history_length {
+            self.history.pop_front();
+        }
+    }
+
+    /// Returns the mutations needed to bring a client at `version` up to date, or `None` if
+    /// they are no longer retained (in which case the client needs a full snapshot).
+    pub fn mutations_since(&self, version: u64) -> Option<Vec<Mutation>> {
+        if version > self.version {
+            return None;
+        }
+
+        if version == self.version {
+            return Some(vec![]);
+        }
+
+        let (oldest, _) = self.history.front()?;
+        if *oldest > version + 1 {
+            return None;
+        }
+
+        Some(
+            self.history
+                .iter()
+                .filter(|(v, _)| *v > version)
+                .flat_map(|(_, mutations)| mutations.iter().cloned())
+                .collect(),
+        )
+    }
+
+    /// Record every intent accepted from now on to `log`.
+    ///
+    /// Each intent is logged before it is applied. If logging it fails, the intent is not
+    /// applied, and [`AperServer::apply`] returns [`IntentError::NotLogged`].
+    pub fn set_intent_log<L: IntentLog<A::Intent> + 'static>(&mut self, log: L) {
+        self.intent_log = Some(Box::new(log));
+    }
+
     pub fn version(&self) -> u64 {
         self.version
     }
 
+    pub fn snapshot(&self) -> ServerSnapshot {
+        ServerSnapshot {
+            version: self.version,
+            mutations: self.state_snapshot(),
+        }
+    }
+
     pub fn state_snapshot(&self) -> Vec<Mutation> {
         // this works because the server only has one layer
         self.map.top_layer_mutations()
@@ -228,7 +437,7 @@ impl<A: Aper> AperServer<A> {
         &mut self,
         intent: &A::Intent,
         metadata: &IntentMetadata,
-    ) -> Result<Vec<Mutation>, A::Error> {
+    ) -> Result<Vec<Mutation>, IntentError<A::Error>> {
         self.map.push_overlay();
 
         let mut sm = A::attach(self.map.handle());
@@ -236,7 +445,20 @@ impl<A: Aper> AperServer<A> {
         if let Err(e) = sm.apply(intent, metadata) {
             // reverse changes.
             self.map.pop_overlay();
-            return Err(e);
+            return Err(IntentError::Rejected(e));
+        }
+
+        if let Some(log) = &mut self.intent_log {
+            let entry = LoggedIntent {
+                intent: intent.clone(),
+                metadata: metadata.clone(),
+                version: self.version + 1,
+            };
+
+            if let Err(err) = log.append(&entry) {
+                self.map.pop_overlay();
+                return Err(IntentError::NotLogged(err));
+            }
         }
 
         self.version += 1;
@@ -244,6 +466,13 @@ impl<A: Aper> AperServer<A> {
         let mutations = self.map.top_layer_mutations();
         self.map.combine_down();
 
+        if self.history_length > 0 {
+            if self.history.len() == self.history_length {
+                self.history.pop_front();
+            }
+            self.history.push_back((self.version, mutations.clone()));
+        }
+
         Ok(mutations)
     }
 
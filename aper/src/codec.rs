@@ -0,0 +1,172 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt::Display;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CodecError {
+    /// The wire format was not compiled in; see the crate's features.
+    Unsupported(WireFormat),
+    Encode(String),
+    Decode(String),
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::Unsupported(format) => {
+                write!(
+                    f,
+                    "Wire format {:?} is not supported by this build.",
+                    format
+                )
+            }
+            CodecError::Encode(error) => write!(f, "Failed to encode message: {}", error),
+            CodecError::Decode(error) => write!(f, "Failed to decode message: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// Serializes protocol messages and intents to and from bytes.
+pub trait Codec {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        bincode::serialize(value).map_err(|err| CodecError::Encode(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        bincode::deserialize(bytes).map_err(|err| CodecError::Decode(err.to_string()))
+    }
+}
+
+/// JSON, for clients that are not written in Rust.
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(value).map_err(|err| CodecError::Encode(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(bytes).map_err(|err| CodecError::Decode(err.to_string()))
+    }
+}
+
+#[cfg(feature = "postcard")]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        postcard::to_stdvec(value).map_err(|err| CodecError::Encode(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        postcard::from_bytes(bytes).map_err(|err| CodecError::Decode(err.to_string()))
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        rmp_serde::to_vec(value).map_err(|err| CodecError::Encode(err.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        rmp_serde::from_slice(bytes).map_err(|err| CodecError::Decode(err.to_string()))
+    }
+}
+
+/// The codec a connection uses, chosen by the client in its handshake.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WireFormat {
+    #[default]
+    Bincode,
+    Json,
+    Postcard,
+    MessagePack,
+}
+
+impl WireFormat {
+    /// Whether this format was compiled in. `Postcard` and `MessagePack` require the
+    /// `postcard` and `msgpack` features.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            WireFormat::Bincode | WireFormat::Json => true,
+            WireFormat::Postcard => cfg!(feature = "postcard"),
+            WireFormat::MessagePack => cfg!(feature = "msgpack"),
+        }
+    }
+}
+
+impl Codec for WireFormat {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            WireFormat::Bincode => Bincode.encode(value),
+            WireFormat::Json => Json.encode(value),
+            #[cfg(feature = "postcard")]
+            WireFormat::Postcard => Postcard.encode(value),
+            #[cfg(feature = "msgpack")]
+            WireFormat::MessagePack => MessagePack.encode(value),
+            #[allow(unreachable_patterns)]
+            format => Err(CodecError::Unsupported(*format)),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            WireFormat::Bincode => Bincode.decode(bytes),
+            WireFormat::Json => Json.decode(bytes),
+            #[cfg(feature = "postcard")]
+            WireFormat::Postcard => Postcard.decode(bytes),
+            #[cfg(feature = "msgpack")]
+            WireFormat::MessagePack => MessagePack.decode(bytes),
+            #[allow(unreachable_patterns)]
+            format => Err(CodecError::Unsupported(*format)),
+        }
+    }
+}
+
+/// A message as sent over a frame-based transport like a WebSocket.
+///
+/// Text frames are always JSON. Binary frames use the connection's [`WireFormat`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// A protocol message that can be sent as a [`Frame`].
+pub trait FramedMessage: Serialize + DeserializeOwned {
+    /// Handshake messages are exchanged before a wire format is agreed, so they are
+    /// always sent as JSON.
+    fn is_handshake(&self) -> bool {
+        false
+    }
+
+    fn encode_frame(&self, format: WireFormat) -> Result<Frame, CodecError> {
+        if self.is_handshake() || format == WireFormat::Json {
+            let text =
+                serde_json::to_string(self).map_err(|err| CodecError::Encode(err.to_string()))?;
+            Ok(Frame::Text(text))
+        } else {
+            Ok(Frame::Binary(format.encode(self)?))
+        }
+    }
+
+    fn decode_frame(frame: &Frame, format: WireFormat) -> Result<Self, CodecError> {
+        match frame {
+            Frame::Text(text) => Json.decode(text.as_bytes()),
+            Frame::Binary(bytes) => format.decode(bytes),
+        }
+    }
+}
@@ -1,23 +1,172 @@
-use crate::{Aper, AperClient, AperServer, IntentMetadata, Store};
+use crate::{
+    codec::{Codec, CodecError, Frame, FramedMessage, WireFormat},
+    Aper, AperClient, AperServer, Bytes, ClientIdentity, Denied, IntentError, IntentMetadata,
+    Mutation, Store, Timestamp,
+};
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
     borrow::Borrow,
-    sync::{atomic::AtomicU32, Arc, Mutex},
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{atomic::AtomicU32, Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
+type ClientCallback = Arc<dyn Fn(&MessageToClient) + Send + Sync>;
+type ErrorEncoder<A> = fn(&<A as Aper>::Error, WireFormat) -> Result<Vec<u8>, CodecError>;
+type Visibility = Arc<dyn Fn(&[Bytes], u32, &ClientIdentity) -> bool + Send + Sync>;
+
+/// The version of the connection protocol implemented by this crate. It is bumped with
+/// each change to the messages that older peers cannot handle.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest protocol version the server accepts from clients.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum MessageToServer {
+    /// Sent first on every connection, to agree on the protocol version and how the rest
+    /// of its messages are encoded. The server responds with `MessageToClientType::Hello`,
+    /// or `MessageToClientType::HandshakeRejected` if the client must be upgraded.
+    ///
+    /// Use [`MessageToServer::handshake`] to construct one.
+    Handshake {
+        /// Missing from clients that predate versioning, which are rejected.
+        #[serde(default)]
+        protocol_version: u32,
+        /// The client's [`Aper::SCHEMA_VERSION`].
+        #[serde(default)]
+        schema_version: u32,
+        wire_format: WireFormat,
+    },
     Intent {
+        /// The intent, encoded in the connection's wire format.
         intent: Vec<u8>,
         client_version: u64,
     },
     RequestState {
+        /// The latest server version the client has seen, or 0 if it has no state. The
+        /// server may respond with only the mutations made since this version.
+        latest_version: u64,
+    },
+    /// Sets (or, if `None`, clears) the client's presence, which is shared with other
+    /// clients but is not part of the state. See [`ClientConnection::set_presence`].
+    SetPresence {
+        /// The presence value, encoded as JSON so that it can be passed on to clients
+        /// using any wire format.
+        presence: Option<String>,
+    },
+    /// Sent by a client reconnecting after its connection dropped, in place of
+    /// `RequestState`.
+    Resume {
+        /// The ID the client was assigned on its previous connection, if any.
+        client_id: Option<u32>,
+        /// The resume token the server issued along with `client_id`. The session is only
+        /// resumed if it matches.
+        #[serde(default)]
+        resume_token: Option<String>,
+        /// The latest server version the client has seen.
         latest_version: u64,
+        /// The latest client version the server is known to have confirmed.
+        client_version: u64,
     },
 }
 
+impl MessageToServer {
+    /// The handshake for a client of `A` at this crate's protocol version.
+    pub fn handshake<A: Aper>(wire_format: WireFormat) -> Self {
+        MessageToServer::Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            schema_version: A::SCHEMA_VERSION,
+            wire_format,
+        }
+    }
+}
+
+/// The reason a server rejected a client's handshake.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum HandshakeRejection {
+    UnsupportedWireFormat(WireFormat),
+    /// The client's protocol version is older than the server accepts.
+    UnsupportedProtocolVersion {
+        min: u32,
+    },
+    /// The client was built against a different schema of the state or intents than the
+    /// server.
+    SchemaMismatch,
+}
+
+impl HandshakeRejection {
+    /// Whether the client must be upgraded (e.g. by reloading the page) to connect.
+    pub fn requires_upgrade(&self) -> bool {
+        !matches!(self, HandshakeRejection::UnsupportedWireFormat(_))
+    }
+}
+
+impl Display for HandshakeRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeRejection::UnsupportedWireFormat(format) => {
+                write!(f, "The server does not support wire format {:?}.", format)
+            }
+            HandshakeRejection::UnsupportedProtocolVersion { min } => {
+                write!(f, "The server requires protocol version {} or later.", min)
+            }
+            HandshakeRejection::SchemaMismatch => {
+                write!(f, "The client is out of date with the server's schema.")
+            }
+        }
+    }
+}
+
+/// A connected client, as listed by [`ServerConnection::clients`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClientInfo {
+    pub client_id: u32,
+    /// When sent to other clients, only the `user` is included, not the claims.
+    pub identity: ClientIdentity,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub connected_at: DateTime<Utc>,
+}
+
+/// An error in a message received from a client. The message is ignored, and the client
+/// is sent a `MessageToClientType::ProtocolError`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProtocolError {
+    /// A frame could not be decoded as a message.
+    MalformedMessage(String),
+    /// An intent could not be decoded in the connection's wire format.
+    MalformedIntent(String),
+    /// The client sent a message before its handshake was accepted.
+    HandshakeRequired,
+    /// The connection was closed by an earlier protocol error; see
+    /// [`ServerConnection::set_disconnect_on_protocol_error`].
+    Disconnected,
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::MalformedMessage(error) => {
+                write!(f, "Malformed message from client: {}", error)
+            }
+            ProtocolError::MalformedIntent(error) => {
+                write!(f, "Malformed intent from client: {}", error)
+            }
+            ProtocolError::HandshakeRequired => {
+                write!(f, "Client sent a message before completing a handshake.")
+            }
+            ProtocolError::Disconnected => {
+                write!(f, "Client was disconnected after a protocol error.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum MessageToClientType {
     Apply {
@@ -25,9 +174,57 @@ pub enum MessageToClientType {
         client_version: Option<u64>,
         server_version: u64,
     },
+    /// The response to `MessageToServer::Handshake`.
     Hello {
         /// The client's assigned ID.
         client_id: u32,
+        /// The protocol version used for the rest of the connection; the lower of the
+        /// client's and the server's.
+        protocol_version: u32,
+        /// A secret that the client must send with `MessageToServer::Resume` to resume
+        /// this session.
+        resume_token: String,
+    },
+    /// Sent instead of `Hello` if the server cannot accept the client's handshake. The
+    /// server ignores any further messages on the connection.
+    HandshakeRejected { reason: HandshakeRejection },
+    /// Sent when the server ignores a message from the client.
+    ProtocolError { error: ProtocolError },
+    /// Sent to a client before the `Apply` that acknowledges an intent the server
+    /// rejected, if the server reports rejections; see
+    /// [`ServerConnection::report_rejected_intents`].
+    IntentRejected {
+        client_version: u64,
+        /// The `Aper::Error`, encoded in the connection's wire format.
+        error: Vec<u8>,
+    },
+    /// Sent to a client before the `Apply` that acknowledges an intent that
+    /// [`Aper::authorize`] denied.
+    IntentDenied { client_version: u64, denied: Denied },
+    /// The connected clients changed. Only sent if the server is set to
+    /// [`ServerConnection::set_broadcast_roster`].
+    Roster { clients: Vec<ClientInfo> },
+    /// Another client's presence changed. `presence` is `None` if it was cleared, or the
+    /// client disconnected. Sent for each connected client after `Hello`.
+    Presence {
+        client_id: u32,
+        presence: Option<String>,
+    },
+    /// The response to `MessageToServer::Resume`.
+    Resumed {
+        /// The client's ID for this connection. This is the client's previous ID if the
+        /// server was able to resume its session.
+        client_id: u32,
+        /// Replaces the resume token from `Hello`, for resuming this session again.
+        resume_token: String,
+        mutations: Vec<crate::Mutation>,
+        /// If true, `mutations` is a full snapshot of the state, which replaces the
+        /// client's state rather than being applied on top of it.
+        snapshot: bool,
+        /// The latest client version the server has processed. Speculative intents after
+        /// this version should be sent again.
+        client_version: u64,
+        server_version: u64,
     },
 }
 
@@ -38,10 +235,39 @@ pub struct MessageToClient {
     pub timestamp: DateTime<Utc>,
 }
 
+impl FramedMessage for MessageToServer {
+    fn is_handshake(&self) -> bool {
+        matches!(self, MessageToServer::Handshake { .. })
+    }
+}
+
+impl FramedMessage for MessageToClient {
+    fn is_handshake(&self) -> bool {
+        matches!(self.message, MessageToClientType::HandshakeRejected { .. })
+    }
+}
+
 pub struct ClientConnection<A: Aper> {
     client: AperClient<A>,
     message_callback: Box<dyn Fn(MessageToServer)>,
     client_id: Option<u32>,
+    resume_token: Option<String>,
+    wire_format: WireFormat,
+    protocol_version: Option<u32>,
+    rejection: Option<HandshakeRejection>,
+    on_rejected: Option<Box<dyn Fn(&A::Intent, &[u8])>>,
+    on_denied: Option<Box<dyn Fn(&A::Intent, &Denied)>>,
+
+    /// This client's presence, sent again when the session resumes.
+    presence: Option<String>,
+    peer_presence: BTreeMap<u32, String>,
+    presence_listeners: Vec<Box<dyn Fn() -> bool>>,
+    roster: Vec<ClientInfo>,
+    roster_listeners: Vec<Box<dyn Fn() -> bool>>,
+
+    /// True between sending a `Resume` and receiving the response. Intents applied in
+    /// the meantime are held back, so they reach the server after the ones being resent.
+    resuming: bool,
 }
 
 impl<A: Aper> ClientConnection<A> {
@@ -49,9 +275,24 @@ impl<A: Aper> ClientConnection<A> {
         client: AperClient<A>,
         message_callback: F,
     ) -> Self {
-        // Request initial state.
+        Self::with_wire_format(client, WireFormat::default(), message_callback)
+    }
+
+    /// Connect using `wire_format` to encode messages and intents. The transport is
+    /// responsible for encoding each message with [`FramedMessage::encode_frame`].
+    pub fn with_wire_format<F: Fn(MessageToServer) + 'static>(
+        client: AperClient<A>,
+        wire_format: WireFormat,
+        message_callback: F,
+    ) -> Self {
+        (message_callback)(MessageToServer::handshake::<A>(wire_format));
+
+        // Request initial state. If the client already has some verified state, the server
+        // can send only what has changed since.
 
-        let init_message = MessageToServer::RequestState { latest_version: 0 };
+        let init_message = MessageToServer::RequestState {
+            latest_version: client.verified_server_version(),
+        };
 
         (message_callback)(init_message);
 
@@ -59,13 +300,85 @@ impl<A: Aper> ClientConnection<A> {
             client,
             message_callback: Box::new(message_callback),
             client_id: None,
+            resume_token: None,
+            wire_format,
+            protocol_version: None,
+            rejection: None,
+            on_rejected: None,
+            on_denied: None,
+            presence: None,
+            peer_presence: BTreeMap::new(),
+            presence_listeners: Vec::new(),
+            roster: Vec::new(),
+            roster_listeners: Vec::new(),
+            resuming: false,
         }
     }
 
+    /// Resume the session over a new connection, after the previous one dropped.
+    ///
+    /// The server is asked for the mutations the client missed. Once they arrive,
+    /// speculative intents that the server did not receive are sent again, in order.
+    pub fn reconnect<F: Fn(MessageToServer) + 'static>(&mut self, message_callback: F) {
+        (message_callback)(MessageToServer::handshake::<A>(self.wire_format));
+        (message_callback)(MessageToServer::Resume {
+            client_id: self.client_id,
+            resume_token: self.resume_token.clone(),
+            latest_version: self.client.verified_server_version(),
+            client_version: self.client.verified_client_version(),
+        });
+
+        self.message_callback = Box::new(message_callback);
+        self.protocol_version = None;
+        self.rejection = None;
+        self.resuming = true;
+
+        // The server sends the presence of the clients connected now after the handshake.
+        self.peer_presence.clear();
+        self.alert_presence();
+    }
+
     pub fn client_id(&self) -> Option<u32> {
         self.client_id
     }
 
+    /// The secret the server issued for resuming this session, once it has accepted the
+    /// handshake.
+    pub fn resume_token(&self) -> Option<&str> {
+        self.resume_token.as_deref()
+    }
+
+    pub fn wire_format(&self) -> WireFormat {
+        self.wire_format
+    }
+
+    /// The protocol version agreed with the server, once it has accepted the handshake.
+    pub fn protocol_version(&self) -> Option<u32> {
+        self.protocol_version
+    }
+
+    /// Why the server rejected the handshake, if it did. If
+    /// [`HandshakeRejection::requires_upgrade`] is true, the application should prompt
+    /// the user to upgrade (e.g. by reloading the page).
+    pub fn handshake_rejection(&self) -> Option<&HandshakeRejection> {
+        self.rejection.as_ref()
+    }
+
+    /// The number of intents applied locally that the server has not yet confirmed.
+    pub fn pending_intents(&self) -> u64 {
+        self.client.speculative_client_version() - self.client.verified_client_version()
+    }
+
+    /// The number of intents that are waiting for the session to resume before they are
+    /// sent (or re-sent) to the server.
+    pub fn held_intents(&self) -> usize {
+        if self.resuming {
+            self.client.speculative_intents().count()
+        } else {
+            0
+        }
+    }
+
     pub fn state(&self) -> A {
         self.client.state()
     }
@@ -74,13 +387,91 @@ impl<A: Aper> ClientConnection<A> {
         self.client.store()
     }
 
+    /// Share `presence` (e.g. a cursor position or a "typing" flag) with the other
+    /// clients. Unlike intents, presence is not versioned or kept in the state; the server
+    /// only keeps each client's latest value until it disconnects.
+    pub fn set_presence<P: Serialize>(&mut self, presence: &P) {
+        let presence = match serde_json::to_string(presence) {
+            Ok(presence) => presence,
+            Err(err) => {
+                tracing::error!(?err, "failed to encode presence");
+                return;
+            }
+        };
+
+        self.presence = Some(presence.clone());
+        (self.message_callback)(MessageToServer::SetPresence {
+            presence: Some(presence),
+        });
+    }
+
+    pub fn clear_presence(&mut self) {
+        self.presence = None;
+        (self.message_callback)(MessageToServer::SetPresence { presence: None });
+    }
+
+    /// The presence of every other connected client that has set one, by client ID.
+    /// Values that can't be decoded as `P` are skipped.
+    pub fn peer_presence<P: DeserializeOwned>(&self) -> BTreeMap<u32, P> {
+        self.peer_presence
+            .iter()
+            .filter_map(|(client_id, presence)| {
+                Some((*client_id, serde_json::from_str(presence).ok()?))
+            })
+            .collect()
+    }
+
+    /// Calls `listener` whenever the presence of another client changes, until it returns
+    /// `false`.
+    pub fn listen_presence<F: Fn() -> bool + 'static>(&mut self, listener: F) {
+        self.presence_listeners.push(Box::new(listener));
+    }
+
+    fn alert_presence(&mut self) {
+        self.presence_listeners.retain(|listener| listener());
+    }
+
+    /// The clients connected to the server, including this one, if the server broadcasts
+    /// its roster.
+    pub fn roster(&self) -> &[ClientInfo] {
+        &self.roster
+    }
+
+    /// Calls `listener` whenever [`Self::roster`] changes, until it returns `false`.
+    pub fn listen_roster<F: Fn() -> bool + 'static>(&mut self, listener: F) {
+        self.roster_listeners.push(Box::new(listener));
+    }
+
+    /// Calls `callback` with each intent from this client that the server's
+    /// [`Aper::authorize`] denies, and the reason.
+    pub fn on_denied<F: Fn(&A::Intent, &Denied) + 'static>(&mut self, callback: F) {
+        self.on_denied = Some(Box::new(callback));
+    }
+
     /// Send an intent to the server, and apply it speculatively to the local state.
+    ///
+    /// An intent that can't be encoded in the connection's wire format (e.g. one with
+    /// non-string map keys, in JSON) could never reach the server, so it is logged and
+    /// dropped without being applied.
     pub fn apply(&mut self, intent: A::Intent) -> Result<(), A::Error> {
+        let encoded = match self.wire_format.encode(&intent) {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                tracing::error!(?err, "failed to encode intent; dropping it");
+                return Ok(());
+            }
+        };
+
         let metadata = IntentMetadata::new(self.client_id, Utc::now());
         let version = self.client.apply(&intent, &metadata)?;
-        let intent = bincode::serialize(&intent).unwrap();
+
+        if self.resuming {
+            // Sent along with the other unconfirmed intents once the session resumes.
+            return Ok(());
+        }
+
         (self.message_callback)(MessageToServer::Intent {
-            intent,
+            intent: encoded,
             client_version: version,
         });
 
@@ -96,17 +487,191 @@ impl<A: Aper> ClientConnection<A> {
             } => {
                 self.client.mutate(mutations, *version, *server_version);
             }
-            MessageToClientType::Hello { client_id } => {
+            MessageToClientType::Hello {
+                client_id,
+                protocol_version,
+                resume_token,
+            } => {
                 self.client_id = Some(*client_id);
+                self.resume_token = Some(resume_token.clone());
+                self.protocol_version = Some(*protocol_version);
             }
+            MessageToClientType::HandshakeRejected { reason } => {
+                tracing::error!(%reason, "server rejected handshake");
+                self.rejection = Some(reason.clone());
+            }
+            MessageToClientType::ProtocolError { error } => {
+                tracing::error!(%error, "server ignored a message");
+            }
+            MessageToClientType::IntentRejected {
+                client_version,
+                error,
+            } => {
+                let Some(on_rejected) = &self.on_rejected else {
+                    return;
+                };
+
+                // The intent is still speculative until the acknowledgement that follows.
+                if let Some((_, intent)) = self
+                    .client
+                    .speculative_intents()
+                    .find(|(version, _)| version == client_version)
+                {
+                    on_rejected(intent, error);
+                }
+            }
+            MessageToClientType::IntentDenied {
+                client_version,
+                denied,
+            } => {
+                let Some(on_denied) = &self.on_denied else {
+                    return;
+                };
+
+                if let Some((_, intent)) = self
+                    .client
+                    .speculative_intents()
+                    .find(|(version, _)| version == client_version)
+                {
+                    on_denied(intent, denied);
+                }
+            }
+            MessageToClientType::Roster { clients } => {
+                self.roster = clients.clone();
+                self.roster_listeners.retain(|listener| listener());
+            }
+            MessageToClientType::Presence {
+                client_id,
+                presence,
+            } => {
+                // A resumed session may be sent its own presence from the old connection.
+                if Some(*client_id) == self.client_id {
+                    return;
+                }
+
+                match presence {
+                    Some(presence) => self.peer_presence.insert(*client_id, presence.clone()),
+                    None => self.peer_presence.remove(client_id),
+                };
+                self.alert_presence();
+            }
+            MessageToClientType::Resumed {
+                client_id,
+                resume_token,
+                mutations,
+                snapshot,
+                client_version,
+                server_version,
+            } => {
+                self.client_id = Some(*client_id);
+                self.resume_token = Some(resume_token.clone());
+                if self.peer_presence.remove(client_id).is_some() {
+                    self.alert_presence();
+                }
+
+                if *snapshot {
+                    self.client
+                        .reset(mutations, Some(*client_version), *server_version);
+                } else {
+                    self.client
+                        .mutate(mutations, Some(*client_version), *server_version);
+                }
+
+                for (version, intent) in self.client.speculative_intents() {
+                    match self.wire_format.encode(intent) {
+                        Ok(intent) => (self.message_callback)(MessageToServer::Intent {
+                            intent,
+                            client_version: version,
+                        }),
+                        Err(err) => {
+                            tracing::error!(?err, version, "failed to encode intent; dropping it")
+                        }
+                    }
+                }
+
+                if let Some(presence) = &self.presence {
+                    (self.message_callback)(MessageToServer::SetPresence {
+                        presence: Some(presence.clone()),
+                    });
+                }
+
+                self.resuming = false;
+            }
+        }
+    }
+}
+
+impl<A: Aper> ClientConnection<A>
+where
+    A::Error: DeserializeOwned,
+{
+    /// Calls `callback` with each intent from this client that the server rejects, and the
+    /// error it was rejected with, so that the application can explain why the change was
+    /// undone. The server must be set to [`ServerConnection::report_rejected_intents`].
+    pub fn on_rejected<F: Fn(&A::Intent, A::Error) + 'static>(&mut self, callback: F) {
+        let wire_format = self.wire_format;
+        self.on_rejected = Some(Box::new(move |intent, error| {
+            match wire_format.decode(error) {
+                Ok(error) => callback(intent, error),
+                Err(err) => tracing::warn!(?err, "failed to decode rejection from server"),
+            }
+        }));
+    }
+}
+
+/// How long a client's session can be resumed after it disconnects, by default.
+pub const DEFAULT_SESSION_EXPIRY: Duration = Duration::from_secs(300);
+
+/// Settings shared by a [`ServerConnection`] and its handles, so that changing them also
+/// applies to clients that are already connected.
+struct ServerConfig<A: Aper> {
+    disconnect_on_protocol_error: bool,
+    encode_error: Option<ErrorEncoder<A>>,
+    visibility: Option<Visibility>,
+    presence_interval: Duration,
+    broadcast_roster: bool,
+    session_expiry: Duration,
+}
+
+impl<A: Aper> Default for ServerConfig<A> {
+    fn default() -> Self {
+        Self {
+            disconnect_on_protocol_error: false,
+            encode_error: None,
+            visibility: None,
+            presence_interval: Duration::ZERO,
+            broadcast_roster: false,
+            session_expiry: DEFAULT_SESSION_EXPIRY,
         }
     }
 }
 
 pub struct ServerConnection<A: Aper> {
-    callbacks: Arc<DashMap<u32, Box<dyn Fn(&MessageToClient) + Send + Sync>>>,
+    callbacks: Arc<DashMap<u32, ClientCallback>>,
     server: Arc<Mutex<AperServer<A>>>,
-    next_client_id: AtomicU32,
+    next_client_id: Arc<AtomicU32>,
+
+    /// The latest client version processed from each client, used to resume sessions.
+    client_versions: Arc<DashMap<u32, u64>>,
+
+    /// The identity of each client, kept after it disconnects so that only the same
+    /// identity can resume its session.
+    identities: Arc<DashMap<u32, ClientIdentity>>,
+
+    /// The token a client must present to resume the session with each client ID.
+    resume_tokens: Arc<DashMap<u32, String>>,
+
+    /// When each client whose session has not been resumed disconnected. Its session is
+    /// forgotten once it expires.
+    disconnected_at: Arc<DashMap<u32, Instant>>,
+
+    config: Arc<RwLock<ServerConfig<A>>>,
+
+    /// The presence of each connected client that has set one, encoded as JSON.
+    presence: Arc<DashMap<u32, String>>,
+
+    /// The clients that have started a session and are still connected.
+    roster: Arc<DashMap<u32, ClientInfo>>,
 }
 
 impl<A: Aper> Default for ServerConnection<A> {
@@ -117,13 +682,32 @@ impl<A: Aper> Default for ServerConnection<A> {
 
 impl<A: Aper> ServerConnection<A> {
     pub fn new() -> Self {
+        Self::from_server(AperServer::new())
+    }
+
+    /// Serve an existing server, e.g. one restored from a snapshot.
+    pub fn from_server(server: AperServer<A>) -> Self {
         Self {
             callbacks: Arc::new(DashMap::new()),
-            server: Arc::new(Mutex::new(AperServer::new())),
-            next_client_id: AtomicU32::new(0),
+            server: Arc::new(Mutex::new(server)),
+            next_client_id: Arc::new(AtomicU32::new(0)),
+            client_versions: Arc::new(DashMap::new()),
+            identities: Arc::new(DashMap::new()),
+            resume_tokens: Arc::new(DashMap::new()),
+            disconnected_at: Arc::new(DashMap::new()),
+            config: Arc::default(),
+            presence: Arc::new(DashMap::new()),
+            roster: Arc::new(DashMap::new()),
         }
     }
 
+    /// If set, a client that sends a malformed message is disconnected: it stops
+    /// receiving updates, and its further messages are ignored. The transport should close
+    /// the connection once [`ServerHandle::is_disconnected`] is true.
+    pub fn set_disconnect_on_protocol_error(&mut self, disconnect: bool) {
+        self.config.write().unwrap().disconnect_on_protocol_error = disconnect;
+    }
+
     pub fn connect<F: Fn(&MessageToClient) + Send + Sync + 'static>(
         &mut self,
         callback: F,
@@ -131,104 +715,668 @@ impl<A: Aper> ServerConnection<A> {
         let client_id = self
             .next_client_id
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.expire_sessions();
 
-        (callback)(&MessageToClient {
-            message: MessageToClientType::Hello { client_id },
-            timestamp: Utc::now(),
-        });
-
-        self.callbacks.insert(client_id, Box::new(callback));
+        let callback: ClientCallback = Arc::new(callback);
+        self.callbacks.insert(client_id, callback.clone());
+        self.identities.insert(client_id, ClientIdentity::default());
+        let resume_token = new_resume_token();
+        self.resume_tokens.insert(client_id, resume_token.clone());
 
         ServerHandle {
             server: self.server.clone(),
             client_id,
+            callback,
             callbacks: self.callbacks.clone(),
+            client_versions: self.client_versions.clone(),
+            identity: ClientIdentity::default(),
+            identities: self.identities.clone(),
+            resume_token,
+            resume_tokens: self.resume_tokens.clone(),
+            disconnected_at: self.disconnected_at.clone(),
+            wire_format: Arc::new(Mutex::new(WireFormat::default())),
+            protocol_version: None,
+            config: self.config.clone(),
+            disconnected: false,
+            presence: self.presence.clone(),
+            presence_sent: None,
+            presence_pending: false,
+            roster: self.roster.clone(),
+            joined: false,
         }
     }
 
+    /// Like [`ServerConnection::connect`], but messages to the client are encoded as
+    /// [`Frame`]s in the wire format the client chose in its handshake.
+    pub fn connect_framed<F: Fn(Frame) + Send + Sync + 'static>(
+        &mut self,
+        callback: F,
+    ) -> ServerHandle<A> {
+        let wire_format = Arc::new(Mutex::new(WireFormat::default()));
+
+        let wire_format_ = wire_format.clone();
+        let mut handle = self.connect(move |message: &MessageToClient| {
+            let format = *wire_format_.lock().unwrap();
+            match message.encode_frame(format) {
+                Ok(frame) => callback(frame),
+                Err(err) => tracing::error!(?err, "failed to encode message to client"),
+            }
+        });
+
+        handle.wire_format = wire_format;
+        handle
+    }
+
+    /// Restrict which parts of the store each client can see. `visible` is called with a
+    /// prefix of the store, and the ID and identity of a client; mutations to prefixes it
+    /// returns `false` for (or that are under such a prefix) are never sent to that client,
+    /// in snapshots or in updates.
+    ///
+    /// Visibility should depend only on the prefix and the client, not on the state: a
+    /// prefix that becomes visible later is not sent until it next changes. Changing it
+    /// does not resend anything to clients that are already connected, so it should be set
+    /// before any clients connect.
+    pub fn set_visibility<F>(&mut self, visible: F)
+    where
+        F: Fn(&[Bytes], u32, &ClientIdentity) -> bool + Send + Sync + 'static,
+    {
+        self.config.write().unwrap().visibility = Some(Arc::new(visible));
+    }
+
+    /// Send each client's presence to the others at most once per `interval`. Updates in
+    /// between are coalesced, and the latest is sent once the interval has passed; see
+    /// [`ServerHandle::flush_presence`].
+    pub fn set_presence_interval(&mut self, interval: Duration) {
+        self.config.write().unwrap().presence_interval = interval;
+    }
+
+    /// Send the list of connected clients to every client whenever it changes, as
+    /// `MessageToClientType::Roster`.
+    pub fn set_broadcast_roster(&mut self, broadcast: bool) {
+        self.config.write().unwrap().broadcast_roster = broadcast;
+    }
+
+    /// How long after a client disconnects it can resume its session, which defaults to
+    /// [`DEFAULT_SESSION_EXPIRY`]. After that, the server forgets the session, and a
+    /// client that reconnects is given a new client ID and a fresh copy of the state.
+    pub fn set_session_expiry(&mut self, expiry: Duration) {
+        self.config.write().unwrap().session_expiry = expiry;
+    }
+
+    /// Forget the sessions of clients that disconnected longer ago than the session expiry.
+    fn expire_sessions(&self) {
+        let expiry = self.config.read().unwrap().session_expiry;
+        expire_sessions(
+            &self.disconnected_at,
+            expiry,
+            &self.client_versions,
+            &self.identities,
+            &self.resume_tokens,
+        );
+    }
+
+    /// The clients that have started a session and are still connected, in order of
+    /// client ID.
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        let mut clients: Vec<_> = self
+            .roster
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        clients.sort_by_key(|client| client.client_id);
+        clients
+    }
+
+    /// Apply an intent that comes from the server itself rather than a client, e.g. from a
+    /// timer or an admin tool, and send the resulting mutations to every client. The
+    /// intent's metadata has no client.
+    pub fn apply_system_intent(&self, intent: &A::Intent) -> Result<(), IntentError<A::Error>> {
+        self.apply_system_intent_at(intent, Utc::now())
+    }
+
+    /// Like [`ServerConnection::apply_system_intent`], with `timestamp` in the intent's
+    /// metadata instead of the current time.
+    pub(crate) fn apply_system_intent_at(
+        &self,
+        intent: &A::Intent,
+        timestamp: Timestamp,
+    ) -> Result<(), IntentError<A::Error>> {
+        let visibility = self.config.read().unwrap().visibility.clone();
+        apply_system_intent(
+            &self.server,
+            &self.callbacks,
+            &self.identities,
+            visibility.as_ref(),
+            intent,
+            timestamp,
+        )
+    }
+
     pub fn state(&self) -> A {
         self.server.lock().unwrap().state()
     }
 }
 
+impl<A: Aper> ServerConnection<A>
+where
+    A::Error: Serialize,
+{
+    /// Send the error to a client when the server rejects one of its intents, as
+    /// `MessageToClientType::IntentRejected`. Otherwise, the client only sees its change
+    /// undone.
+    pub fn report_rejected_intents(&mut self) {
+        self.config.write().unwrap().encode_error =
+            Some(|error, wire_format| wire_format.encode(error));
+    }
+}
+
 pub struct ServerHandle<A: Aper> {
     client_id: u32,
     server: Arc<Mutex<AperServer<A>>>,
-    callbacks: Arc<DashMap<u32, Box<dyn Fn(&MessageToClient) + Send + Sync>>>,
+    callback: ClientCallback,
+    callbacks: Arc<DashMap<u32, ClientCallback>>,
+    client_versions: Arc<DashMap<u32, u64>>,
+    identity: ClientIdentity,
+    identities: Arc<DashMap<u32, ClientIdentity>>,
+    resume_token: String,
+    resume_tokens: Arc<DashMap<u32, String>>,
+    disconnected_at: Arc<DashMap<u32, Instant>>,
+
+    /// Shared with the callback created by [`ServerConnection::connect_framed`].
+    wire_format: Arc<Mutex<WireFormat>>,
+
+    /// The protocol version agreed in the handshake. Until the handshake is accepted,
+    /// other messages are ignored.
+    protocol_version: Option<u32>,
+
+    /// Shared with the [`ServerConnection`], and read whenever it is used.
+    config: Arc<RwLock<ServerConfig<A>>>,
+    disconnected: bool,
+
+    presence: Arc<DashMap<u32, String>>,
+    /// When this client's presence was last sent to the others.
+    presence_sent: Option<Instant>,
+    /// Whether this client's presence changed since it was last sent.
+    presence_pending: bool,
+
+    roster: Arc<DashMap<u32, ClientInfo>>,
+    /// Whether the client has started its session, with `RequestState` or `Resume`.
+    joined: bool,
 }
 
 impl<A: Aper> ServerHandle<A> {
-    pub fn receive(&mut self, message: &MessageToServer) {
+    pub fn client_id(&self) -> u32 {
+        self.client_id
+    }
+
+    /// Set who the client is, e.g. from the credentials it connected with. This should be
+    /// called before any messages from the client are received; clients are anonymous
+    /// until it is.
+    pub fn set_identity(&mut self, identity: ClientIdentity) {
+        self.identities.insert(self.client_id, identity.clone());
+        self.identity = identity;
+    }
+
+    pub fn identity(&self) -> &ClientIdentity {
+        &self.identity
+    }
+
+    /// The wire format the client chose in its handshake.
+    pub fn wire_format(&self) -> WireFormat {
+        *self.wire_format.lock().unwrap()
+    }
+
+    /// The protocol version agreed in the handshake, if it has been accepted.
+    pub fn protocol_version(&self) -> Option<u32> {
+        self.protocol_version
+    }
+
+    /// Whether the client was disconnected by a protocol error.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    /// Decode a frame received from the client in its wire format, and handle it.
+    pub fn receive_frame(&mut self, frame: &Frame) -> Result<(), ProtocolError> {
+        match MessageToServer::decode_frame(frame, self.wire_format()) {
+            Ok(message) => self.receive(&message),
+            Err(err) => self.fail(ProtocolError::MalformedMessage(err.to_string())),
+        }
+    }
+
+    fn report_rejection(&self, client_version: u64, error: &A::Error) {
+        let Some(encode_error) = self.config.read().unwrap().encode_error else {
+            return;
+        };
+
+        match encode_error(error, self.wire_format()) {
+            Ok(error) => (self.callback)(&MessageToClient {
+                message: MessageToClientType::IntentRejected {
+                    client_version,
+                    error,
+                },
+                timestamp: Utc::now(),
+            }),
+            Err(err) => tracing::error!(?err, "failed to encode rejection"),
+        }
+    }
+
+    fn visible_mutations(&self, mutations: &[Mutation], client_id: u32) -> Vec<Mutation> {
+        visible_mutations(
+            self.config.read().unwrap().visibility.as_ref(),
+            &self.identities,
+            mutations,
+            client_id,
+        )
+    }
+
+    fn report_denial(&self, client_version: u64, denied: Denied) {
+        tracing::info!(
+            client_id = self.client_id,
+            reason = denied.reason,
+            "denied intent from client"
+        );
+
+        (self.callback)(&MessageToClient {
+            message: MessageToClientType::IntentDenied {
+                client_version,
+                denied,
+            },
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// Send this client's latest presence to the others, if it changed and was held back
+    /// by [`ServerConnection::set_presence_interval`]. This is also done whenever a message
+    /// is received from the client, but transports should call it again at
+    /// [`ServerHandle::next_presence_flush`] so that the last update before the client
+    /// goes quiet is not held back indefinitely.
+    pub fn flush_presence(&mut self) {
+        if !self.presence_pending {
+            return;
+        }
+
+        if let Some(sent) = self.presence_sent {
+            if sent.elapsed() < self.config.read().unwrap().presence_interval {
+                return;
+            }
+        }
+
+        let message = MessageToClient {
+            message: MessageToClientType::Presence {
+                client_id: self.client_id,
+                presence: self
+                    .presence
+                    .get(&self.client_id)
+                    .map(|presence| presence.clone()),
+            },
+            timestamp: Utc::now(),
+        };
+
+        for entry in self.callbacks.iter() {
+            if *entry.key() != self.client_id {
+                entry.value()(&message);
+            }
+        }
+
+        self.presence_sent = Some(Instant::now());
+        self.presence_pending = false;
+    }
+
+    /// When [`ServerHandle::flush_presence`] will send the presence update that is being
+    /// held back, or `None` if there is none.
+    pub fn next_presence_flush(&self) -> Option<Instant> {
+        if !self.presence_pending {
+            return None;
+        }
+
+        let interval = self.config.read().unwrap().presence_interval;
+        Some(
+            self.presence_sent
+                .map_or_else(Instant::now, |sent| sent + interval),
+        )
+    }
+
+    /// Send the presence of every other client to this one.
+    fn send_peer_presence(&self) {
+        for entry in self.presence.iter() {
+            if *entry.key() == self.client_id {
+                continue;
+            }
+
+            (self.callback)(&MessageToClient {
+                message: MessageToClientType::Presence {
+                    client_id: *entry.key(),
+                    presence: Some(entry.value().clone()),
+                },
+                timestamp: Utc::now(),
+            });
+        }
+    }
+
+    /// Add the client to the roster once its session has started, and let the state react
+    /// with [`Aper::client_joined`]. A resumed session whose previous connection has not
+    /// been dropped yet is already on the roster.
+    fn join(&mut self) {
+        if self.joined {
+            return;
+        }
+        self.joined = true;
+
+        let info = ClientInfo {
+            client_id: self.client_id,
+            identity: self.identity.clone(),
+            connected_at: Utc::now(),
+        };
+
+        if self.roster.insert(self.client_id, info.clone()).is_some() {
+            return;
+        }
+
+        let intent = self.server.lock().unwrap().state().client_joined(&info);
+        if let Some(intent) = intent {
+            self.apply_system_intent(&intent);
+        }
+
+        self.send_roster();
+    }
+
+    fn apply_system_intent(&self, intent: &A::Intent) {
+        let visibility = self.config.read().unwrap().visibility.clone();
+        if let Err(err) = apply_system_intent(
+            &self.server,
+            &self.callbacks,
+            &self.identities,
+            visibility.as_ref(),
+            intent,
+            Utc::now(),
+        ) {
+            tracing::warn!(%err, "system intent was not applied");
+        }
+    }
+
+    fn send_roster(&self) {
+        if !self.config.read().unwrap().broadcast_roster {
+            return;
+        }
+
+        let mut clients: Vec<_> = self
+            .roster
+            .iter()
+            .map(|entry| ClientInfo {
+                identity: ClientIdentity {
+                    user: entry.identity.user.clone(),
+                    claims: BTreeMap::new(),
+                },
+                ..entry.value().clone()
+            })
+            .collect();
+        clients.sort_by_key(|client| client.client_id);
+
+        let message = MessageToClient {
+            message: MessageToClientType::Roster { clients },
+            timestamp: Utc::now(),
+        };
+
+        for entry in self.callbacks.iter() {
+            entry.value()(&message);
+        }
+    }
+
+    /// Stop sending updates to this client, clear its presence, and remove it from the
+    /// roster. Does nothing if another handle has taken over its client ID.
+    fn disconnect(&mut self) {
+        let removed = self.callbacks.remove_if(&self.client_id, |_, callback| {
+            Arc::ptr_eq(callback, &self.callback)
+        });
+
+        if removed.is_none() {
+            return;
+        }
+
+        self.disconnected_at.insert(self.client_id, Instant::now());
+        let expiry = self.config.read().unwrap().session_expiry;
+        expire_sessions(
+            &self.disconnected_at,
+            expiry,
+            &self.client_versions,
+            &self.identities,
+            &self.resume_tokens,
+        );
+
+        if self.presence.remove(&self.client_id).is_some() {
+            // Sent straight away, since no later update will follow it.
+            self.presence_sent = None;
+            self.presence_pending = true;
+            self.flush_presence();
+        }
+
+        if let Some((_, info)) = self.roster.remove(&self.client_id) {
+            let intent = self.server.lock().unwrap().state().client_left(&info);
+            if let Some(intent) = intent {
+                self.apply_system_intent(&intent);
+            }
+
+            self.send_roster();
+        }
+    }
+
+    /// Report a protocol error to the client, and disconnect it if configured to.
+    fn fail(&mut self, error: ProtocolError) -> Result<(), ProtocolError> {
+        if self.disconnected {
+            return Err(ProtocolError::Disconnected);
+        }
+
+        tracing::warn!(client_id = self.client_id, %error, "ignoring message from client");
+
+        (self.callback)(&MessageToClient {
+            message: MessageToClientType::ProtocolError {
+                error: error.clone(),
+            },
+            timestamp: Utc::now(),
+        });
+
+        if self.config.read().unwrap().disconnect_on_protocol_error {
+            self.disconnected = true;
+            self.disconnect();
+        }
+
+        Err(error)
+    }
+
+    /// Take over the ID a client was assigned on a previous connection, if `resume_token`
+    /// is the token issued with it and the client has the same identity. Returns whether
+    /// the ID was taken over.
+    fn resume_client_id(&mut self, client_id: u32, resume_token: Option<&str>) -> bool {
+        if client_id == self.client_id {
+            return true;
+        }
+
+        let token_matches = resume_token.is_some_and(|resume_token| {
+            self.resume_tokens
+                .get(&client_id)
+                .is_some_and(|token| *token == resume_token)
+        });
+        if !token_matches {
+            return false;
+        }
+
+        if !self
+            .identities
+            .get(&client_id)
+            .is_some_and(|identity| *identity == self.identity)
+        {
+            return false;
+        }
+
+        // The previous connection may not have been dropped yet; this replaces its
+        // callback, and its handle will leave this one in place when it is dropped.
+        self.callbacks.remove(&self.client_id);
+        self.callbacks.insert(client_id, self.callback.clone());
+        self.identities.remove(&self.client_id);
+        self.resume_tokens.remove(&self.client_id);
+        self.client_versions.remove(&self.client_id);
+        self.disconnected_at.remove(&client_id);
+        self.client_id = client_id;
+
+        // A token is only good for one resumption.
+        self.resume_token = new_resume_token();
+        self.resume_tokens
+            .insert(client_id, self.resume_token.clone());
+
+        true
+    }
+
+    fn check_handshake(
+        protocol_version: u32,
+        schema_version: u32,
+        wire_format: WireFormat,
+    ) -> Result<(), HandshakeRejection> {
+        // Newer clients are accepted, and fall back to the server's protocol version.
+        if protocol_version < MIN_PROTOCOL_VERSION {
+            return Err(HandshakeRejection::UnsupportedProtocolVersion {
+                min: MIN_PROTOCOL_VERSION,
+            });
+        }
+
+        if schema_version != A::SCHEMA_VERSION {
+            return Err(HandshakeRejection::SchemaMismatch);
+        }
+
+        if !wire_format.is_supported() {
+            return Err(HandshakeRejection::UnsupportedWireFormat(wire_format));
+        }
+
+        Ok(())
+    }
+
+    /// Handle a message from the client. If it is invalid, it is ignored and the client is
+    /// sent a `MessageToClientType::ProtocolError`; other clients are unaffected.
+    pub fn receive(&mut self, message: &MessageToServer) -> Result<(), ProtocolError> {
+        if self.disconnected {
+            return Err(ProtocolError::Disconnected);
+        }
+
+        if self.protocol_version.is_none() && !message.is_handshake() {
+            return self.fail(ProtocolError::HandshakeRequired);
+        }
+
+        self.flush_presence();
+
         match message {
+            MessageToServer::Handshake {
+                protocol_version,
+                schema_version,
+                wire_format,
+            } => {
+                let message =
+                    match Self::check_handshake(*protocol_version, *schema_version, *wire_format) {
+                        Ok(()) => {
+                            let protocol_version = (*protocol_version).min(PROTOCOL_VERSION);
+                            self.protocol_version = Some(protocol_version);
+                            *self.wire_format.lock().unwrap() = *wire_format;
+                            MessageToClientType::Hello {
+                                client_id: self.client_id,
+                                protocol_version,
+                                resume_token: self.resume_token.clone(),
+                            }
+                        }
+                        Err(reason) => {
+                            self.protocol_version = None;
+                            MessageToClientType::HandshakeRejected { reason }
+                        }
+                    };
+
+                let accepted = matches!(message, MessageToClientType::Hello { .. });
+                (self.callback)(&MessageToClient {
+                    message,
+                    timestamp: Utc::now(),
+                });
+
+                if accepted {
+                    self.send_peer_presence();
+                }
+            }
+            MessageToServer::SetPresence { presence } => {
+                match presence {
+                    Some(presence) => self.presence.insert(self.client_id, presence.clone()),
+                    None => self
+                        .presence
+                        .remove(&self.client_id)
+                        .map(|(_, presence)| presence),
+                };
+
+                self.presence_pending = true;
+                self.flush_presence();
+            }
             MessageToServer::Intent {
                 intent,
                 client_version,
             } => {
-                let intent = bincode::deserialize(intent).unwrap();
+                let intent = match self.wire_format().decode(intent) {
+                    Ok(intent) => intent,
+                    Err(err) => return self.fail(ProtocolError::MalformedIntent(err.to_string())),
+                };
                 let mut server_borrow = self.server.lock().unwrap();
+                self.client_versions.insert(self.client_id, *client_version);
                 let metadata = IntentMetadata::new(Some(self.client_id), Utc::now());
-                let Ok(mutations) = server_borrow.apply(&intent, &metadata) else {
+                let result =
+                    match server_borrow
+                        .state()
+                        .authorize(&intent, &metadata, &self.identity)
+                    {
+                        Ok(()) => {
+                            server_borrow
+                                .apply(&intent, &metadata)
+                                .map_err(|error| match error {
+                                    IntentError::Rejected(error) => {
+                                        self.report_rejection(*client_version, &error)
+                                    }
+                                    IntentError::NotLogged(err) => tracing::error!(
+                                        %err,
+                                        client_id = self.client_id,
+                                        "failed to log intent from client"
+                                    ),
+                                })
+                        }
+                        Err(denied) => {
+                            self.report_denial(*client_version, denied);
+                            Err(())
+                        }
+                    };
+
+                let Ok(mutations) = result else {
                     // still need to ack the client.
 
-                    if let Some(callback) = self.callbacks.get(&self.client_id) {
-                        let time = Utc::now();
-                        let message = MessageToClient {
-                            message: MessageToClientType::Apply {
-                                mutations: vec![],
-                                client_version: Some(*client_version),
-                                server_version: server_borrow.version(),
-                            },
-                            timestamp: time,
-                        };
-
-                        callback(&message);
-                    }
+                    let time = Utc::now();
+                    let message = MessageToClient {
+                        message: MessageToClientType::Apply {
+                            mutations: vec![],
+                            client_version: Some(*client_version),
+                            server_version: server_borrow.version(),
+                        },
+                        timestamp: time,
+                    };
 
-                    return;
+                    (self.callback)(&message);
+
+                    return Ok(());
                 };
 
                 let version = server_borrow.version();
                 let time = Utc::now();
 
-                let message_to_others = MessageToClient {
-                    message: MessageToClientType::Apply {
-                        mutations: mutations.clone(),
-                        client_version: None,
-                        server_version: version,
-                    },
-                    timestamp: time,
-                };
-
-                let message_to_sender = MessageToClient {
-                    message: MessageToClientType::Apply {
-                        mutations: mutations.clone(),
-                        client_version: Some(*client_version),
-                        server_version: version,
-                    },
-                    timestamp: time,
-                };
-
                 for entry in self.callbacks.iter() {
                     let (other_client_id, callback) = entry.pair();
-                    if *other_client_id == self.client_id {
-                        callback(&message_to_sender);
-                    } else {
-                        callback(&message_to_others);
-                    }
-                }
-            }
-            MessageToServer::RequestState { .. } => {
-                let server = self.server.lock().unwrap();
-                let c = server.borrow();
-                let mutations = c.state_snapshot();
 
-                if let Some(callback) = self.callbacks.get(&self.client_id) {
-                    let time = Utc::now();
+                    // Clients that can't see any of the mutations still receive the new
+                    // version, to keep their version numbers in step with the server.
                     let message = MessageToClient {
                         message: MessageToClientType::Apply {
-                            mutations,
-                            client_version: None,
-                            server_version: c.version(),
+                            mutations: self.visible_mutations(&mutations, *other_client_id),
+                            client_version: (*other_client_id == self.client_id)
+                                .then_some(*client_version),
+                            server_version: version,
                         },
                         timestamp: time,
                     };
@@ -236,12 +1384,232 @@ impl<A: Aper> ServerHandle<A> {
                     callback(&message);
                 }
             }
+            MessageToServer::RequestState { latest_version } => {
+                let server = self.server.lock().unwrap();
+                let c = server.borrow();
+
+                // A client with no state gets a snapshot, which is never larger than the
+                // accumulated history.
+                let mutations = if *latest_version == 0 {
+                    None
+                } else {
+                    c.mutations_since(*latest_version)
+                };
+                let mutations = mutations.unwrap_or_else(|| c.state_snapshot());
+                let mutations = self.visible_mutations(&mutations, self.client_id);
+
+                let time = Utc::now();
+                let message = MessageToClient {
+                    message: MessageToClientType::Apply {
+                        mutations,
+                        client_version: None,
+                        server_version: c.version(),
+                    },
+                    timestamp: time,
+                };
+
+                (self.callback)(&message);
+            }
+            MessageToServer::Resume {
+                client_id,
+                resume_token,
+                latest_version,
+                client_version,
+            } => {
+                let resumed = client_id.is_some_and(|client_id| {
+                    self.resume_client_id(client_id, resume_token.as_deref())
+                });
+
+                // Prefer the server's record of what it processed, since the client may not
+                // have received the confirmation of its last intents before disconnecting.
+                let client_version = if resumed {
+                    self.client_versions
+                        .get(&self.client_id)
+                        .map_or(*client_version, |version| (*version).max(*client_version))
+                } else {
+                    *client_version
+                };
+
+                let server = self.server.lock().unwrap();
+                let (mutations, snapshot) = match server.mutations_since(*latest_version) {
+                    Some(mutations) => (mutations, false),
+                    None => (server.state_snapshot(), true),
+                };
+                let mutations = self.visible_mutations(&mutations, self.client_id);
+
+                let message = MessageToClient {
+                    message: MessageToClientType::Resumed {
+                        client_id: self.client_id,
+                        resume_token: self.resume_token.clone(),
+                        mutations,
+                        snapshot,
+                        client_version,
+                        server_version: server.version(),
+                    },
+                    timestamp: Utc::now(),
+                };
+
+                (self.callback)(&message);
+            }
+        }
+
+        if matches!(
+            message,
+            MessageToServer::RequestState { .. } | MessageToServer::Resume { .. }
+        ) {
+            self.join();
         }
+
+        Ok(())
     }
 }
 
+/// A random token for resuming a session, which other clients can't guess.
+fn new_resume_token() -> String {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("failed to generate a resume token");
+    format!("{:032x}", u128::from_be_bytes(bytes))
+}
+
+/// The mutations that the client `client_id` is allowed to see.
+fn visible_mutations(
+    visibility: Option<&Visibility>,
+    identities: &DashMap<u32, ClientIdentity>,
+    mutations: &[Mutation],
+    client_id: u32,
+) -> Vec<Mutation> {
+    let Some(visible) = visibility else {
+        return mutations.to_vec();
+    };
+
+    let identity = identities
+        .get(&client_id)
+        .map(|identity| identity.clone())
+        .unwrap_or_default();
+
+    mutations
+        .iter()
+        .filter(|mutation| {
+            (1..=mutation.prefix.len())
+                .all(|len| visible(&mutation.prefix[..len], client_id, &identity))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Forget the client version, identity and resume token of each client that disconnected
+/// at least `expiry` ago.
+fn expire_sessions(
+    disconnected_at: &DashMap<u32, Instant>,
+    expiry: Duration,
+    client_versions: &DashMap<u32, u64>,
+    identities: &DashMap<u32, ClientIdentity>,
+    resume_tokens: &DashMap<u32, String>,
+) {
+    disconnected_at.retain(|client_id, disconnected_at| {
+        if disconnected_at.elapsed() < expiry {
+            return true;
+        }
+
+        client_versions.remove(client_id);
+        identities.remove(client_id);
+        resume_tokens.remove(client_id);
+        false
+    });
+}
+
+/// Apply an intent that did not come from a client, and send the mutations to every
+/// client.
+fn apply_system_intent<A: Aper>(
+    server: &Mutex<AperServer<A>>,
+    callbacks: &DashMap<u32, ClientCallback>,
+    identities: &DashMap<u32, ClientIdentity>,
+    visibility: Option<&Visibility>,
+    intent: &A::Intent,
+    timestamp: Timestamp,
+) -> Result<(), IntentError<A::Error>> {
+    let mut server = server.lock().unwrap();
+    let mutations = server.apply(intent, &IntentMetadata::new(None, timestamp))?;
+    let version = server.version();
+    let time = Utc::now();
+
+    for entry in callbacks.iter() {
+        let (client_id, callback) = entry.pair();
+        callback(&MessageToClient {
+            message: MessageToClientType::Apply {
+                mutations: visible_mutations(visibility, identities, &mutations, *client_id),
+                client_version: None,
+                server_version: version,
+            },
+            timestamp: time,
+        });
+    }
+
+    Ok(())
+}
+
 impl<A: Aper> Drop for ServerHandle<A> {
     fn drop(&mut self) {
-        self.callbacks.remove(&self.client_id);
+        // Another handle may have taken over this client ID to resume the session.
+        self.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data_structures::Atom;
+
+    impl Aper for Atom<u32> {
+        type Intent = u32;
+        type Error = ();
+
+        const SCHEMA_VERSION: u32 = 1;
+
+        fn apply(&mut self, intent: &u32, _metadata: &IntentMetadata) -> Result<(), ()> {
+            self.set(*intent);
+            Ok(())
+        }
+    }
+
+    fn keys<V>(map: &DashMap<u32, V>) -> Vec<u32> {
+        let mut keys: Vec<_> = map.iter().map(|entry| *entry.key()).collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn sessions_are_forgotten_once_they_expire() {
+        let mut server = ServerConnection::<Atom<u32>>::new();
+        server.set_session_expiry(Duration::from_millis(20));
+
+        let mut alice = server.connect(|_| {});
+        alice
+            .receive(&MessageToServer::handshake::<Atom<u32>>(
+                WireFormat::default(),
+            ))
+            .unwrap();
+        alice
+            .receive(&MessageToServer::Intent {
+                intent: bincode::serialize(&1u32).unwrap(),
+                client_version: 1,
+            })
+            .unwrap();
+        drop(alice);
+
+        // Alice's session is kept for a while, in case she reconnects.
+        let bob = server.connect(|_| {});
+        assert_eq!(keys(&server.client_versions), vec![0]);
+        assert_eq!(keys(&server.identities), vec![0, 1]);
+        assert_eq!(keys(&server.resume_tokens), vec![0, 1]);
+        drop(bob);
+
+        std::thread::sleep(Duration::from_millis(30));
+        let _carol = server.connect(|_| {});
+
+        assert!(server.client_versions.is_empty());
+        assert_eq!(keys(&server.identities), vec![2]);
+        assert_eq!(keys(&server.resume_tokens), vec![2]);
+        assert!(server.disconnected_at.is_empty());
     }
 }
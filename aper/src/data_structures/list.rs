@@ -0,0 +1,445 @@
+use crate::{AperSync, StoreHandle, StoreIterator};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+const ORDER_PREFIX: &[u8] = b"order";
+const POSITIONS_PREFIX: &[u8] = b"positions";
+const ITEMS_PREFIX: &[u8] = b"items";
+
+/// Stable identifier of an element in a [`List`].
+///
+/// Like the IDs in the to-do example of the guide, these are generated on the client
+/// (e.g. from a UUID) and sent as part of the intent, so that the server and the
+/// client's speculative state agree on which element an intent refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ListItemId(pub u128);
+
+impl ListItemId {
+    fn to_bytes(self) -> Bytes {
+        Bytes::from(self.0.to_be_bytes().to_vec())
+    }
+
+    /// Returns `None` if `bytes` is not a valid ID, which can only happen if the store
+    /// was modified other than through a [`List`].
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let Ok(bytes) = <[u8; 16]>::try_from(bytes) else {
+            tracing::warn!(len = bytes.len(), "skipping malformed list item ID");
+            return None;
+        };
+
+        Some(ListItemId(u128::from_be_bytes(bytes)))
+    }
+}
+
+/// An ordered sequence of `AperSync` values.
+///
+/// Each element is stored under its own child prefix, keyed by its [`ListItemId`].
+/// Ordering is tracked separately by assigning every element a position key that sorts
+/// between its neighbours. Positions are computed from the element IDs involved in an
+/// insert or move, rather than from indices, so re-applying a speculative intent on top
+/// of newer server state places the element next to the same neighbours.
+pub struct List<T: AperSync> {
+    map: StoreHandle,
+    _phantom: std::marker::PhantomData<T>,
+}
+
+impl<T> Clone for List<T>
+where
+    T: AperSync,
+{
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: AperSync> AperSync for List<T> {
+    fn attach(map: StoreHandle) -> Self {
+        Self {
+            map,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Listeners are alerted when elements are inserted, moved, or deleted. Changes to
+    /// the elements themselves are reported to listeners on the elements.
+    fn listen<F: Fn() -> bool + 'static + Send + Sync>(&self, listener: F) {
+        self.order().listen(listener)
+    }
+}
+
+impl<T: AperSync> List<T> {
+    fn order(&self) -> StoreHandle {
+        self.map.clone().child(Bytes::from_static(ORDER_PREFIX))
+    }
+
+    fn positions(&self) -> StoreHandle {
+        self.map.clone().child(Bytes::from_static(POSITIONS_PREFIX))
+    }
+
+    fn items(&self) -> StoreHandle {
+        self.map.clone().child(Bytes::from_static(ITEMS_PREFIX))
+    }
+
+    fn position_of(&self, id: &ListItemId) -> Option<Bytes> {
+        self.positions().get(&id.to_bytes())
+    }
+
+    /// Returns the positions of the elements immediately before and after `position`.
+    fn neighbours(&self, position: &Bytes) -> (Option<Bytes>, Option<Bytes>) {
+        let mut before = None;
+
+        for (key, _) in self.order().iter() {
+            if key < *position {
+                before = Some(key);
+            } else if key > *position {
+                return (before, Some(key));
+            }
+        }
+
+        (before, None)
+    }
+
+    fn place(&mut self, id: &ListItemId, position: Bytes) {
+        let id_bytes = id.to_bytes();
+
+        if let Some(old_position) = self.positions().get(&id_bytes) {
+            self.order().delete(old_position);
+        }
+
+        self.order().set(position.clone(), id_bytes.clone());
+        self.positions().set(id_bytes, position);
+    }
+
+    fn place_between(&mut self, id: &ListItemId, lo: Option<Bytes>, hi: Option<Bytes>) {
+        let position = position_between(lo.as_deref(), hi.as_deref(), &id.to_bytes());
+        self.place(id, position);
+    }
+
+    fn first_position(&self, excluding: &ListItemId) -> Option<Bytes> {
+        let excluding = excluding.to_bytes();
+        self.order()
+            .iter()
+            .find(|(_, id)| id != &excluding)
+            .map(|(position, _)| position)
+    }
+
+    fn last_position(&self, excluding: &ListItemId) -> Option<Bytes> {
+        let excluding = excluding.to_bytes();
+        self.order()
+            .iter()
+            .filter(|(_, id)| id != &excluding)
+            .last()
+            .map(|(position, _)| position)
+    }
+
+    /// Returns the element with the given ID, if it is in the list.
+    pub fn get(&self, id: &ListItemId) -> Option<T> {
+        self.position_of(id)?;
+        Some(T::attach(self.items().child(id.to_bytes())))
+    }
+
+    pub fn contains(&self, id: &ListItemId) -> bool {
+        self.position_of(id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Returns the IDs of the elements, in list order.
+    pub fn ids(&self) -> Vec<ListItemId> {
+        self.iter().map(|(id, _)| id).collect()
+    }
+
+    /// Iterates over `(id, element)` pairs, in list order.
+    pub fn iter(&self) -> ListIter<T> {
+        ListIter {
+            iter: self.order().iter(),
+            items: self.items(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Inserts a new (default) element at the start of the list and returns it.
+    ///
+    /// If an element with the same ID already exists, it is moved instead.
+    pub fn insert_first(&mut self, id: &ListItemId) -> T {
+        let hi = self.first_position(id);
+        self.place_between(id, None, hi);
+        T::attach(self.items().child(id.to_bytes()))
+    }
+
+    /// Inserts a new (default) element at the end of the list and returns it.
+    ///
+    /// If an element with the same ID already exists, it is moved instead.
+    pub fn insert_last(&mut self, id: &ListItemId) -> T {
+        let lo = self.last_position(id);
+        self.place_between(id, lo, None);
+        T::attach(self.items().child(id.to_bytes()))
+    }
+
+    /// Inserts a new (default) element directly before `anchor` and returns it.
+    ///
+    /// Returns `None` if `anchor` is not in the list (for example, because another
+    /// client deleted it before this intent reached the server).
+    pub fn insert_before(&mut self, id: &ListItemId, anchor: &ListItemId) -> Option<T> {
+        self.place_before(id, anchor)
+            .then(|| T::attach(self.items().child(id.to_bytes())))
+    }
+
+    /// Inserts a new (default) element directly after `anchor` and returns it.
+    ///
+    /// Returns `None` if `anchor` is not in the list.
+    pub fn insert_after(&mut self, id: &ListItemId, anchor: &ListItemId) -> Option<T> {
+        self.place_after(id, anchor)
+            .then(|| T::attach(self.items().child(id.to_bytes())))
+    }
+
+    /// Moves an element so that it directly precedes `anchor`.
+    ///
+    /// Returns `false` (leaving the list unchanged) if either element is not in the
+    /// list, or if `anchor` is the element being moved.
+    pub fn move_before(&mut self, id: &ListItemId, anchor: &ListItemId) -> bool {
+        self.contains(id) && self.place_before(id, anchor)
+    }
+
+    /// Moves an element so that it directly follows `anchor`.
+    ///
+    /// Returns `false` (leaving the list unchanged) if either element is not in the
+    /// list, or if `anchor` is the element being moved.
+    pub fn move_after(&mut self, id: &ListItemId, anchor: &ListItemId) -> bool {
+        self.contains(id) && self.place_after(id, anchor)
+    }
+
+    fn place_before(&mut self, id: &ListItemId, anchor: &ListItemId) -> bool {
+        if id == anchor {
+            return false;
+        }
+
+        let Some(hi) = self.position_of(anchor) else {
+            return false;
+        };
+
+        let (lo, _) = self.neighbours(&hi);
+        let own_position = self.position_of(id);
+        let lo = match lo {
+            // The element is already directly before the anchor; skip over it.
+            Some(lo) if Some(&lo) == own_position.as_ref() => self.neighbours(&lo).0,
+            lo => lo,
+        };
+
+        self.place_between(id, lo, Some(hi));
+        true
+    }
+
+    fn place_after(&mut self, id: &ListItemId, anchor: &ListItemId) -> bool {
+        if id == anchor {
+            return false;
+        }
+
+        let Some(lo) = self.position_of(anchor) else {
+            return false;
+        };
+
+        let (_, hi) = self.neighbours(&lo);
+        let own_position = self.position_of(id);
+        let hi = match hi {
+            // The element is already directly after the anchor; skip over it.
+            Some(hi) if Some(&hi) == own_position.as_ref() => self.neighbours(&hi).1,
+            hi => hi,
+        };
+
+        self.place_between(id, Some(lo), hi);
+        true
+    }
+
+    /// Moves an element to the start of the list. Returns `false` if it is not in the list.
+    pub fn move_first(&mut self, id: &ListItemId) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+
+        self.insert_first(id);
+        true
+    }
+
+    /// Moves an element to the end of the list. Returns `false` if it is not in the list.
+    pub fn move_last(&mut self, id: &ListItemId) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+
+        self.insert_last(id);
+        true
+    }
+
+    /// Removes an element and all of its data from the list.
+    pub fn delete(&mut self, id: &ListItemId) {
+        let id_bytes = id.to_bytes();
+
+        if let Some(position) = self.positions().get(&id_bytes) {
+            self.order().delete(position);
+        }
+
+        self.positions().delete(id_bytes.clone());
+        self.items().delete_child(id_bytes);
+    }
+}
+
+pub struct ListIter<T: AperSync> {
+    iter: StoreIterator,
+    items: StoreHandle,
+    _phantom: std::marker::PhantomData<T>,
+}
+
+impl<T: AperSync> Iterator for ListIter<T> {
+    type Item = (ListItemId, T);
+
+    /// Entries with a malformed ID (e.g. from a mutation sent by a buggy peer) are skipped.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (_, id_bytes) = self.iter.next()?;
+            if let Some(id) = ListItemId::from_bytes(&id_bytes) {
+                return Some((id, T::attach(self.items.child(id_bytes))));
+            }
+        }
+    }
+}
+
+/// Generates a position key that sorts strictly between `lo` and `hi` (either of which
+/// may be open-ended), followed by `suffix`.
+///
+/// Callers pass a suffix that is unique to the element being placed, so two clients
+/// concurrently inserting at the same place end up with a deterministic order instead
+/// of colliding.
+pub(crate) fn position_between(lo: Option<&[u8]>, hi: Option<&[u8]>, suffix: &[u8]) -> Bytes {
+    let lo = lo.unwrap_or(&[]);
+    let mut hi = hi;
+    let mut result = Vec::new();
+
+    for i in 0.. {
+        // `lo` is treated as if it were padded with zeros, and an unbounded `hi` as if it
+        // were padded with 256s.
+        let l = lo.get(i).copied().map_or(0, u16::from);
+        let h = match hi {
+            Some(hi_bytes) => match hi_bytes.get(i) {
+                Some(b) => u16::from(*b),
+                None => {
+                    // Only reachable if `hi` is `lo` followed by zeros, which keys generated
+                    // here never are. Fall back to appending after `lo`.
+                    hi = None;
+                    256
+                }
+            },
+            None => 256,
+        };
+
+        if h - l >= 2 {
+            result.push(((l + h) / 2) as u8);
+            break;
+        }
+
+        result.push(l as u8);
+
+        if h - l == 1 {
+            // The result is now strictly below `hi`, so any continuation stays below it.
+            hi = None;
+        }
+    }
+
+    result.extend_from_slice(suffix);
+    Bytes::from(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data_structures::Atom;
+
+    fn values(list: &List<Atom<String>>) -> Vec<String> {
+        list.iter().map(|(_, value)| value.get()).collect()
+    }
+
+    #[test]
+    fn position_between_is_ordered() {
+        let a = position_between(None, None, b"1");
+        let b = position_between(Some(&a), None, b"2");
+        let c = position_between(Some(&a), Some(&b), b"3");
+        let d = position_between(None, Some(&a), b"4");
+
+        assert!(d < a);
+        assert!(a < c);
+        assert!(c < b);
+    }
+
+    #[test]
+    fn insert_and_iterate() {
+        let store = crate::Store::default();
+        let mut list = List::<Atom<String>>::attach(store.handle());
+
+        list.insert_last(&ListItemId(1)).set("b".to_string());
+        list.insert_first(&ListItemId(2)).set("a".to_string());
+        list.insert_last(&ListItemId(3)).set("d".to_string());
+        list.insert_after(&ListItemId(4), &ListItemId(1))
+            .unwrap()
+            .set("c".to_string());
+
+        assert_eq!(values(&list), vec!["a", "b", "c", "d"]);
+        assert_eq!(
+            list.ids(),
+            vec![ListItemId(2), ListItemId(1), ListItemId(4), ListItemId(3)]
+        );
+        assert_eq!(list.len(), 4);
+        assert!(list
+            .insert_before(&ListItemId(5), &ListItemId(99))
+            .is_none());
+    }
+
+    #[test]
+    fn move_and_delete() {
+        let store = crate::Store::default();
+        let mut list = List::<Atom<String>>::attach(store.handle());
+
+        for (i, value) in ["a", "b", "c"].iter().enumerate() {
+            list.insert_last(&ListItemId(i as u128))
+                .set(value.to_string());
+        }
+
+        assert!(list.move_before(&ListItemId(2), &ListItemId(0)));
+        assert_eq!(values(&list), vec!["c", "a", "b"]);
+
+        assert!(list.move_after(&ListItemId(2), &ListItemId(0)));
+        assert_eq!(values(&list), vec!["a", "c", "b"]);
+
+        assert!(list.move_last(&ListItemId(0)));
+        assert_eq!(values(&list), vec!["c", "b", "a"]);
+
+        assert!(!list.move_after(&ListItemId(0), &ListItemId(0)));
+
+        list.delete(&ListItemId(1));
+        assert_eq!(values(&list), vec!["c", "a"]);
+        assert!(list.get(&ListItemId(1)).is_none());
+        assert!(!list.contains(&ListItemId(1)));
+    }
+
+    #[test]
+    fn malformed_ids_are_skipped() {
+        let store = crate::Store::default();
+        let mut list = List::<Atom<String>>::attach(store.handle());
+
+        list.insert_last(&ListItemId(1)).set("a".to_string());
+        list.order()
+            .set(Bytes::from_static(b"\xff"), Bytes::from_static(b"bad"));
+
+        assert_eq!(values(&list), vec!["a"]);
+        assert_eq!(list.ids(), vec![ListItemId(1)]);
+        assert_eq!(list.len(), 1);
+    }
+}
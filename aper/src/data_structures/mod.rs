@@ -1,9 +1,15 @@
 pub mod atom;
 pub mod atom_map;
 pub mod fixed_array;
+pub mod list;
 pub mod map;
+pub mod switch;
+pub mod text;
 
 pub use atom::Atom;
 pub use atom_map::AtomMap;
 pub use fixed_array::FixedArray;
+pub use list::{List, ListItemId};
 pub use map::Map;
+pub use switch::Switch;
+pub use text::{Text, TextCursor, TextEdit};
@@ -0,0 +1,66 @@
+use crate::{AperSync, AperSyncEnum, StoreHandle};
+use bytes::Bytes;
+
+/// Holds an `AperSync` enum and allows changing its active variant.
+///
+/// An enum that derives `AperSync` can be used as a field directly, which is enough to
+/// `match` on it. Wrapping it in a `Switch` also allows switching to another variant.
+pub struct Switch<E: AperSyncEnum> {
+    map: StoreHandle,
+    _phantom: std::marker::PhantomData<E>,
+}
+
+impl<E> Clone for Switch<E>
+where
+    E: AperSyncEnum,
+{
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<E: AperSyncEnum> AperSync for Switch<E> {
+    fn attach(map: StoreHandle) -> Self {
+        Self {
+            map,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Listeners are alerted when the active variant changes. Changes within a variant
+    /// are reported to listeners on its fields.
+    fn listen<F: Fn() -> bool + 'static + Send + Sync>(&self, listener: F) {
+        self.map.listen(listener)
+    }
+}
+
+impl<E: AperSyncEnum> Switch<E> {
+    pub fn get(&self) -> E {
+        E::attach(self.map.clone())
+    }
+
+    /// The name of the active variant.
+    pub fn variant(&self) -> &'static str {
+        self.get().variant()
+    }
+
+    /// Makes `variant` the active variant and returns the enum with its (default) fields.
+    ///
+    /// The data of the previously active variant is deleted, so switching to the variant
+    /// that is already active resets it. Returns `None` if the enum has no variant with
+    /// the given name.
+    pub fn set_variant(&mut self, variant: &str) -> Option<E> {
+        let variant = E::variants().iter().find(|v| **v == variant)?;
+
+        let current = self.variant();
+        self.map
+            .delete_child(Bytes::from_static(current.as_bytes()));
+        self.map
+            .set(Bytes::new(), Bytes::from_static(variant.as_bytes()));
+
+        Some(self.get())
+    }
+}
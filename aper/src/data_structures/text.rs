@@ -0,0 +1,284 @@
+use super::list::position_between;
+use crate::{AperSync, StoreHandle};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// A location between two characters of a [`Text`].
+///
+/// A cursor is anchored to the character before it rather than to an index, so it keeps
+/// pointing at the same place when edits from other clients are applied to the text.
+/// The default cursor is the start of the text.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextCursor(Option<Bytes>);
+
+/// An edit to a [`Text`], suitable for including in an intent.
+///
+/// Edits are created with [`Text::insert_edit`] and [`Text::delete_edit`]. They refer to
+/// the characters around the edit rather than to indices, so an edit made against a
+/// client's (possibly stale) view of the text still applies to the intended place once it
+/// reaches the server, and concurrent edits give the same result in either order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextEdit(EditKind);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum EditKind {
+    Insert {
+        after: Option<Bytes>,
+        before: Option<Bytes>,
+        id: u128,
+        text: String,
+    },
+    Delete {
+        keys: Vec<Bytes>,
+    },
+}
+
+/// A string that can be edited concurrently by multiple clients.
+///
+/// Each character is stored as its own entry, keyed by a position that sorts between its
+/// neighbours. Inserting text only adds entries and deleting text only removes them, so
+/// edits to different parts of the text never overwrite each other.
+///
+/// Positions are never rebalanced, so they get longer as text is inserted between
+/// characters that are already close together. Typing one character at a time grows the
+/// keys by about a byte for every eight characters typed in the same place (e.g. to over
+/// a hundred bytes after a thousand characters), and deleting text does not shrink the
+/// keys of the characters that remain.
+#[derive(Clone)]
+pub struct Text {
+    map: StoreHandle,
+}
+
+impl AperSync for Text {
+    fn attach(map: StoreHandle) -> Self {
+        Self { map }
+    }
+
+    fn listen<F: Fn() -> bool + 'static + Send + Sync>(&self, listener: F) {
+        self.map.listen(listener)
+    }
+}
+
+impl Text {
+    /// The text as a string. Invalid UTF-8 in the store (e.g. from a mutation sent by a
+    /// buggy peer) is replaced with U+FFFD.
+    pub fn value(&self) -> String {
+        self.map
+            .iter()
+            .map(|(_, value)| String::from_utf8_lossy(&value).into_owned())
+            .collect()
+    }
+
+    /// The length of the text, in characters.
+    pub fn len(&self) -> usize {
+        self.map.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.iter().next().is_none()
+    }
+
+    /// Returns a cursor located after the first `index` characters. Indices past the end
+    /// of the text are clamped to the end.
+    pub fn cursor(&self, index: usize) -> TextCursor {
+        if index == 0 {
+            return TextCursor(None);
+        }
+
+        let mut last = None;
+        for (key, _) in self.map.iter().take(index) {
+            last = Some(key);
+        }
+
+        TextCursor(last)
+    }
+
+    /// Returns the current index of a cursor, in characters.
+    ///
+    /// If the character a cursor is anchored to has been deleted, the cursor is placed
+    /// where that character used to be.
+    pub fn index(&self, cursor: &TextCursor) -> usize {
+        let Some(anchor) = &cursor.0 else {
+            return 0;
+        };
+
+        self.map.iter().take_while(|(key, _)| key <= anchor).count()
+    }
+
+    /// Creates an edit that inserts `text` before the character currently at `index`.
+    ///
+    /// `id` must be unique to this edit (e.g. generated from a UUID on the client); it
+    /// orders concurrent inserts at the same place. This does not modify the text;
+    /// include the edit in an intent and [`Text::apply`] it.
+    pub fn insert_edit(&self, index: usize, text: &str, id: u128) -> TextEdit {
+        let after = self.cursor(index).0;
+        let before = self.map.iter().nth(index).map(|(key, _)| key);
+
+        TextEdit(EditKind::Insert {
+            after,
+            before,
+            id,
+            text: text.to_string(),
+        })
+    }
+
+    /// Creates an edit that deletes the characters currently in `range`.
+    ///
+    /// Only the characters in the range when the edit is created are deleted; text that
+    /// another client inserts into the range concurrently is kept. This does not modify the
+    /// text; include the edit in an intent and [`Text::apply`] it.
+    pub fn delete_edit(&self, range: Range<usize>) -> TextEdit {
+        let keys = self
+            .map
+            .iter()
+            .skip(range.start)
+            .take(range.end.saturating_sub(range.start))
+            .map(|(key, _)| key)
+            .collect();
+
+        TextEdit(EditKind::Delete { keys })
+    }
+
+    pub fn apply(&mut self, edit: &TextEdit) {
+        match &edit.0 {
+            EditKind::Insert {
+                after,
+                before,
+                id,
+                text,
+            } => {
+                // Every character of the insert shares a prefix that ends with the edit's
+                // ID, followed by its index, so the inserted text stays together when
+                // another insert is made at the same place concurrently.
+                let prefix =
+                    position_between(after.as_deref(), before.as_deref(), &id.to_be_bytes());
+
+                for (i, c) in text.chars().enumerate() {
+                    let mut position = prefix.to_vec();
+                    position.extend_from_slice(&(i as u32).to_be_bytes());
+                    self.map.set(
+                        Bytes::from(position),
+                        Bytes::from(c.to_string().into_bytes()),
+                    );
+                }
+            }
+            EditKind::Delete { keys } => {
+                for key in keys {
+                    self.map.delete(key.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Store;
+
+    #[test]
+    fn insert_and_delete() {
+        let store = Store::default();
+        let mut text = Text::attach(store.handle());
+
+        text.apply(&text.insert_edit(0, "hello", 1));
+        text.apply(&text.insert_edit(5, " world", 2));
+        text.apply(&text.insert_edit(0, ">> ", 3));
+        assert_eq!(text.value(), ">> hello world");
+        assert_eq!(text.len(), 14);
+
+        text.apply(&text.delete_edit(0..3));
+        text.apply(&text.delete_edit(5..11));
+        assert_eq!(text.value(), "hello");
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let store_a = Store::default();
+        let store_b = Store::default();
+        let mut text_a = Text::attach(store_a.handle());
+        let mut text_b = Text::attach(store_b.handle());
+
+        let initial = text_a.insert_edit(0, "ac", 1);
+        text_a.apply(&initial);
+        text_b.apply(&initial);
+
+        // Both edits are created against the same version of the text, then applied in
+        // opposite orders.
+        let edit_1 = text_a.insert_edit(1, "b", 2);
+        let edit_2 = text_b.insert_edit(1, "B", 3);
+        let edit_3 = text_b.delete_edit(1..2);
+
+        text_a.apply(&edit_1);
+        text_a.apply(&edit_2);
+        text_a.apply(&edit_3);
+
+        text_b.apply(&edit_3);
+        text_b.apply(&edit_2);
+        text_b.apply(&edit_1);
+
+        assert_eq!(text_a.value(), text_b.value());
+        assert_eq!(text_a.value(), "abB");
+
+        // Concurrent inserts at the same place are ordered by their ID.
+        let edit_4 = text_a.insert_edit(3, "2", 5);
+        let edit_5 = text_b.insert_edit(3, "1", 4);
+
+        text_a.apply(&edit_4);
+        text_a.apply(&edit_5);
+
+        text_b.apply(&edit_5);
+        text_b.apply(&edit_4);
+
+        assert_eq!(text_a.value(), text_b.value());
+        assert_eq!(text_a.value(), "abB12");
+
+        // Concurrent inserts of several characters at the same place are not interleaved.
+        let edit_6 = text_a.insert_edit(1, "xy", 6);
+        let edit_7 = text_b.insert_edit(1, "ZW", 7);
+
+        text_a.apply(&edit_6);
+        text_a.apply(&edit_7);
+
+        text_b.apply(&edit_7);
+        text_b.apply(&edit_6);
+
+        assert_eq!(text_a.value(), text_b.value());
+        assert_eq!(text_a.value(), "axyZWbB12");
+    }
+
+    #[test]
+    fn cursor_survives_remote_edits() {
+        let store = Store::default();
+        let mut text = Text::attach(store.handle());
+
+        text.apply(&text.insert_edit(0, "hello world", 1));
+
+        // Caret is after "hello".
+        let caret = text.cursor(5);
+
+        text.apply(&text.insert_edit(0, "oh, ", 2));
+        assert_eq!(text.index(&caret), 9);
+
+        text.apply(&text.insert_edit(15, "!", 3));
+        assert_eq!(text.index(&caret), 9);
+
+        // Deleting the anchor character moves the caret to where it used to be.
+        text.apply(&text.delete_edit(7..9));
+        assert_eq!(text.index(&caret), 7);
+        assert_eq!(text.value(), "oh, hel world!");
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let store = Store::default();
+        let mut text = Text::attach(store.handle());
+
+        text.apply(&text.insert_edit(0, "ab", 1));
+        let key = text.cursor(1).0.unwrap();
+        text.map.set(key, Bytes::from_static(b"\xff"));
+
+        assert_eq!(text.value(), "\u{fffd}b");
+    }
+}
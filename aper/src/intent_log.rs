@@ -0,0 +1,197 @@
+use crate::{Aper, AperServer, IntentError, IntentMetadata, ServerSnapshot};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+/// An intent that was accepted by an [`AperServer`], along with the server version that
+/// applying it produced.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LoggedIntent<I> {
+    pub intent: I,
+    pub metadata: IntentMetadata,
+    pub version: u64,
+}
+
+/// A sink for intents accepted by an [`AperServer`], set with
+/// [`AperServer::set_intent_log`].
+///
+/// Together with a [`ServerSnapshot`], the logged intents can rebuild the server with
+/// [`replay`].
+pub trait IntentLog<I>: Send {
+    fn append(&mut self, entry: &LoggedIntent<I>) -> std::io::Result<()>;
+}
+
+/// An [`IntentLog`] that appends entries to a file.
+///
+/// Each entry is written as a little-endian `u64` length followed by the bincode-encoded
+/// entry. Entries are handed to the operating system as they are appended.
+pub struct FileIntentLog<I> {
+    file: File,
+    _phantom: PhantomData<fn(I)>,
+}
+
+impl<I: Serialize + DeserializeOwned> FileIntentLog<I> {
+    /// Opens a log file for appending, creating it if it does not exist.
+    ///
+    /// A truncated entry at the end of the file (e.g. from a crash during a write) is
+    /// removed, so that the entries appended after it can be read.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        let (_, end) = read_entries(BufReader::new(&file))?;
+        if end < file.metadata()?.len() {
+            tracing::warn!("removing truncated entry at end of intent log");
+            file.set_len(end)?;
+        }
+        file.seek(SeekFrom::Start(end))?;
+
+        Ok(Self {
+            file,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Reads every entry from a log file.
+    ///
+    /// A truncated entry at the end of the file (e.g. from a crash during a write) is
+    /// ignored.
+    pub fn read(path: impl AsRef<Path>) -> std::io::Result<Vec<LoggedIntent<I>>> {
+        let (entries, _) = read_entries(BufReader::new(File::open(path)?))?;
+
+        entries
+            .iter()
+            .map(|entry| {
+                bincode::deserialize(entry)
+                    .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))
+            })
+            .collect()
+    }
+}
+
+/// Reads the encoded entries from a log file, and returns them along with the length of
+/// the file up to the end of the last complete entry.
+fn read_entries(mut reader: impl Read) -> std::io::Result<(Vec<Vec<u8>>, u64)> {
+    let mut entries = Vec::new();
+    let mut end = 0;
+
+    loop {
+        let mut len = [0u8; 8];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+
+        let mut entry = vec![0u8; u64::from_le_bytes(len) as usize];
+        match reader.read_exact(&mut entry) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                tracing::warn!("ignoring truncated entry at end of intent log");
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+
+        end += 8 + entry.len() as u64;
+        entries.push(entry);
+    }
+
+    Ok((entries, end))
+}
+
+impl<I: Serialize> IntentLog<I> for FileIntentLog<I> {
+    fn append(&mut self, entry: &LoggedIntent<I>) -> std::io::Result<()> {
+        let entry = bincode::serialize(entry)
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err))?;
+
+        // Write the length and the entry in one call, so that a partial write can only
+        // truncate the last entry.
+        let mut buffer = Vec::with_capacity(entry.len() + 8);
+        buffer.extend_from_slice(&(entry.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(&entry);
+
+        self.file.write_all(&buffer)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    /// The log skips a version, or the server produced a different version than the one
+    /// that was logged.
+    VersionMismatch { expected: u64, found: u64 },
+    /// The server rejected an intent that was accepted when it was logged.
+    IntentRejected { version: u64, error: String },
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::VersionMismatch { expected, found } => write!(
+                f,
+                "Replayed intent produced version {}, but the log recorded version {}.",
+                found, expected
+            ),
+            ReplayError::IntentRejected { version, error } => write!(
+                f,
+                "Logged intent for version {} was rejected during replay: {}",
+                version, error
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Rebuilds a server from a snapshot and the intents logged after it.
+///
+/// Entries at or below the snapshot's version are skipped, so the full log can be passed
+/// even if the snapshot was taken part of the way through it. Every remaining entry must
+/// produce exactly the version that was logged with it.
+pub fn replay<A: Aper>(
+    snapshot: &ServerSnapshot,
+    entries: impl IntoIterator<Item = LoggedIntent<A::Intent>>,
+) -> Result<AperServer<A>, ReplayError> {
+    let mut server = AperServer::from_snapshot(snapshot);
+
+    for entry in entries {
+        if entry.version <= server.version() {
+            continue;
+        }
+
+        if entry.version != server.version() + 1 {
+            return Err(ReplayError::VersionMismatch {
+                expected: entry.version,
+                found: server.version() + 1,
+            });
+        }
+
+        server
+            .apply(&entry.intent, &entry.metadata)
+            .map_err(|err| ReplayError::IntentRejected {
+                version: entry.version,
+                error: match err {
+                    IntentError::Rejected(err) => format!("{:?}", err),
+                    IntentError::NotLogged(err) => err.to_string(),
+                },
+            })?;
+
+        if server.version() != entry.version {
+            return Err(ReplayError::VersionMismatch {
+                expected: entry.version,
+                found: server.version(),
+            });
+        }
+    }
+
+    Ok(server)
+}
@@ -1,16 +1,21 @@
 #![allow(clippy::type_complexity)]
 
 mod aper;
+pub mod codec;
 pub mod connection;
 pub mod data_structures;
+pub mod intent_log;
 mod listener;
+pub mod scheduler;
 mod store;
+pub mod transport;
 pub use aper::*;
 pub use aper_derive::AperSync;
 pub use bytes::Bytes;
 use chrono::serde::ts_milliseconds;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 pub use store::*;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -37,3 +42,46 @@ impl IntentMetadata {
         IntentMetadata::new(None, Utc::now())
     }
 }
+
+/// Who a client is, as established by the server when it connects (e.g. from a session
+/// cookie or an access token). Passed to [`Aper::authorize`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ClientIdentity {
+    /// A stable identifier for the user, which (unlike the client ID) is the same across
+    /// connections. `None` for anonymous clients.
+    pub user: Option<String>,
+    /// Application-defined claims about the user, e.g. `"role" => "editor"`.
+    pub claims: BTreeMap<String, String>,
+}
+
+impl ClientIdentity {
+    pub fn user(user: impl Into<String>) -> ClientIdentity {
+        ClientIdentity {
+            user: Some(user.into()),
+            claims: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_claim(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.claims.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn claim(&self, key: &str) -> Option<&str> {
+        self.claims.get(key).map(String::as_str)
+    }
+}
+
+/// Returned by [`Aper::authorize`] when a client may not apply an intent.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Denied {
+    pub reason: String,
+}
+
+impl Denied {
+    pub fn new(reason: impl Into<String>) -> Denied {
+        Denied {
+            reason: reason.into(),
+        }
+    }
+}
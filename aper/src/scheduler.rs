@@ -0,0 +1,172 @@
+use crate::{connection::ServerConnection, Aper, IntentMetadata, Timestamp};
+use chrono::Duration;
+use std::collections::BTreeMap;
+
+/// Applies the intents a state declares with [`Aper::scheduled_events`] when they are due.
+///
+/// The scheduler does not keep time itself: the server runtime calls
+/// [`Scheduler::run_due`] with the current time, and uses [`Scheduler::next_event`] to
+/// decide when to call it again (e.g. by setting a timer). [`VirtualClock`] drives it with
+/// simulated time, for tests.
+pub struct Scheduler<A: Aper> {
+    events: BTreeMap<String, (A::Intent, IntentMetadata)>,
+
+    /// The time of each event that has been applied, so that an event the state still
+    /// declares after it was applied is not applied again.
+    fired: BTreeMap<String, Timestamp>,
+}
+
+impl<A: Aper> Default for Scheduler<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Aper> Scheduler<A> {
+    pub fn new() -> Self {
+        Self {
+            events: BTreeMap::new(),
+            fired: BTreeMap::new(),
+        }
+    }
+
+    /// Re-read the scheduled events from `state`, which should be called after every
+    /// change to it. Returns whether the time of the next event changed.
+    pub fn update(&mut self, state: &A) -> bool {
+        let next = self.next_event();
+
+        self.events = state.scheduled_events();
+        let events = &self.events;
+        self.fired.retain(|key, timestamp| {
+            events
+                .get(key)
+                .is_some_and(|(_, metadata)| metadata.timestamp == *timestamp)
+        });
+
+        next != self.next_event()
+    }
+
+    /// The time of the earliest event that has not been applied yet.
+    pub fn next_event(&self) -> Option<Timestamp> {
+        self.pending()
+            .map(|(_, _, metadata)| metadata.timestamp)
+            .min()
+    }
+
+    /// Apply every event that is due at `now`, earliest first, and send the mutations to
+    /// every client. Returns the keys of the events that were applied.
+    ///
+    /// The schedule is re-read after each event, so an event can cancel or reschedule
+    /// others. Only the events that were due when this is called are applied; an event
+    /// that schedules another one that is already due leaves it for the next call, so
+    /// that a pair of events that keep scheduling each other cannot keep this running.
+    ///
+    /// An event that is rejected (or can't be recorded in the intent log) is logged and
+    /// not retried, unless the state schedules it
+    /// again at a different time.
+    pub fn run_due(&mut self, connection: &ServerConnection<A>, now: Timestamp) -> Vec<String> {
+        self.update(&connection.state());
+
+        let mut due: Vec<(Timestamp, String)> = self
+            .pending()
+            .filter(|(_, _, metadata)| metadata.timestamp <= now)
+            .map(|(key, _, metadata)| (metadata.timestamp, key.clone()))
+            .collect();
+        due.sort();
+
+        let mut applied = Vec::new();
+        for (timestamp, key) in due {
+            // An earlier event may have cancelled or rescheduled this one.
+            let intent = match self.events.get(&key) {
+                Some((intent, metadata))
+                    if metadata.timestamp == timestamp
+                        && self.fired.get(&key) != Some(&timestamp) =>
+                {
+                    intent.clone()
+                }
+                _ => continue,
+            };
+
+            self.fired.insert(key.clone(), timestamp);
+            match connection.apply_system_intent_at(&intent, timestamp) {
+                Ok(()) => applied.push(key),
+                Err(err) => tracing::warn!(key, %err, "scheduled event was not applied"),
+            }
+
+            self.update(&connection.state());
+        }
+
+        applied
+    }
+
+    fn pending(&self) -> impl Iterator<Item = (&String, &A::Intent, &IntentMetadata)> {
+        self.events
+            .iter()
+            .filter(|(key, (_, metadata))| self.fired.get(*key) != Some(&metadata.timestamp))
+            .map(|(key, (intent, metadata))| (key, intent, metadata))
+    }
+}
+
+/// How many times [`VirtualClock::advance`] runs the scheduler without the clock moving
+/// before it gives up on the events that are due.
+pub const MAX_RUNS_AT_ONCE: usize = 1000;
+
+/// Runs a [`Scheduler`] against simulated time, so that tests can advance the clock
+/// instead of waiting.
+pub struct VirtualClock<A: Aper> {
+    scheduler: Scheduler<A>,
+    now: Timestamp,
+}
+
+impl<A: Aper> VirtualClock<A> {
+    pub fn new(now: Timestamp) -> Self {
+        Self {
+            scheduler: Scheduler::new(),
+            now,
+        }
+    }
+
+    pub fn now(&self) -> Timestamp {
+        self.now
+    }
+
+    /// Advance the clock by `duration`, applying each event that becomes due in order.
+    /// The clock is at each event's time while it is applied. Returns the keys of the
+    /// events that were applied.
+    ///
+    /// Events that keep scheduling other events at or before the current time are applied
+    /// at most [`MAX_RUNS_AT_ONCE`] times, after which the clock moves on.
+    pub fn advance(&mut self, connection: &ServerConnection<A>, duration: Duration) -> Vec<String> {
+        let until = self.now + duration;
+        let mut fired = Vec::new();
+        let mut runs_at_once = 0;
+
+        loop {
+            self.scheduler.update(&connection.state());
+
+            match self.scheduler.next_event() {
+                Some(next) if next <= until => {
+                    if next <= self.now {
+                        runs_at_once += 1;
+                        if runs_at_once > MAX_RUNS_AT_ONCE {
+                            tracing::warn!(
+                                now = %self.now,
+                                "scheduled events keep scheduling events that are already due"
+                            );
+                            break;
+                        }
+                    } else {
+                        runs_at_once = 0;
+                    }
+
+                    self.now = self.now.max(next);
+                    fired.extend(self.scheduler.run_due(connection, self.now));
+                }
+                _ => break,
+            }
+        }
+
+        self.now = until;
+        fired
+    }
+}
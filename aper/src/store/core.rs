@@ -64,6 +64,19 @@ impl Store {
         layer.layer.entry(prefix.to_vec()).or_default();
     }
 
+    /// Remove every entry and overlay, leaving a single empty layer. Every prefix that
+    /// was present is marked dirty, so listeners are alerted on the next
+    /// [`Store::notify_dirty`].
+    pub fn clear(&self) {
+        let prefixes = self.prefixes();
+
+        let mut layer = StoreLayer::default();
+        layer.dirty.extend(prefixes);
+
+        let mut layers = self.inner.layers.write().unwrap();
+        *layers = vec![layer];
+    }
+
     pub fn push_overlay(&self) {
         let mut layers = self.inner.layers.write().unwrap();
         layers.push(StoreLayer::default());
@@ -87,7 +100,7 @@ impl Store {
             let mut layers = self.inner.layers.write().unwrap();
             for layer in layers.iter_mut() {
                 let new_prefixes = std::mem::take(&mut layer.dirty);
-                dirty_prefixes.extend(new_prefixes.into_iter());
+                dirty_prefixes.extend(new_prefixes);
             }
         }
 
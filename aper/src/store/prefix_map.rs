@@ -10,10 +10,64 @@ pub enum PrefixMapValue {
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum PrefixMap {
-    Children(BTreeMap<Bytes, PrefixMapValue>),
+    Children(#[serde(with = "children")] BTreeMap<Bytes, PrefixMapValue>),
     DeletedPrefixMap,
 }
 
+/// Binary formats serialize children as a map keyed by bytes, as the derived
+/// implementation did. Human-readable formats like JSON only allow string keys in maps, so
+/// they use the hex encoding of each key instead.
+mod children {
+    use super::PrefixMapValue;
+    use crate::Bytes;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S: Serializer>(
+        map: &BTreeMap<Bytes, PrefixMapValue>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return map.serialize(serializer);
+        }
+
+        serializer.collect_map(map.iter().map(|(key, value)| (to_hex(key), value)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<Bytes, PrefixMapValue>, D::Error> {
+        if !deserializer.is_human_readable() {
+            return BTreeMap::deserialize(deserializer);
+        }
+
+        BTreeMap::<String, PrefixMapValue>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| {
+                let key = from_hex(&key)
+                    .ok_or_else(|| D::Error::custom(format!("invalid hex key {:?}", key)))?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn from_hex(hex: &str) -> Option<Bytes> {
+        if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .map(Bytes::from)
+    }
+}
+
 impl PrefixMap {
     pub fn get(&self, key: &Bytes) -> Option<PrefixMapValue> {
         match self {
@@ -46,3 +100,44 @@ impl Default for PrefixMap {
         Self::Children(BTreeMap::new())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn children() -> BTreeMap<Bytes, PrefixMapValue> {
+        let mut children = BTreeMap::new();
+        children.insert(
+            Bytes::from_static(b"\x00\xff"),
+            PrefixMapValue::Value(Bytes::from_static(b"a")),
+        );
+        children.insert(Bytes::from_static(b"b"), PrefixMapValue::Deleted);
+        children
+    }
+
+    #[test]
+    fn binary_encoding_is_a_map() {
+        #[derive(Serialize)]
+        enum Expected {
+            Children(BTreeMap<Bytes, PrefixMapValue>),
+        }
+
+        assert_eq!(
+            bincode::serialize(&PrefixMap::Children(children())).unwrap(),
+            bincode::serialize(&Expected::Children(children())).unwrap(),
+        );
+    }
+
+    #[test]
+    fn json_encoding_has_hex_keys() {
+        let json = serde_json::to_string(&PrefixMap::Children(children())).unwrap();
+        assert!(json.contains("\"00ff\""));
+
+        let PrefixMap::Children(decoded) = serde_json::from_str(&json).unwrap() else {
+            panic!("Expected children.");
+        };
+        assert_eq!(decoded, children());
+
+        assert!(serde_json::from_str::<PrefixMap>(r#"{"Children":{"0g":"Deleted"}}"#).is_err());
+    }
+}
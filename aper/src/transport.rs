@@ -0,0 +1,308 @@
+use crate::{
+    codec::{Frame, FramedMessage, WireFormat},
+    connection::{
+        ClientConnection, MessageToClient, MessageToServer, ServerConnection, ServerHandle,
+    },
+    Aper, AperClient,
+};
+use std::{
+    fmt::Display,
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransportError {
+    /// The transport was closed, by either end.
+    Closed,
+    /// The transport failed, e.g. because of an I/O error or a malformed frame.
+    Failed(String),
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportError::Closed => write!(f, "Transport closed."),
+            TransportError::Failed(error) => write!(f, "Transport failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransportEvent<M> {
+    Message(M),
+    /// The transport failed, e.g. because its socket did. No further events are
+    /// delivered after an error. A [`ChannelTransport`] can't fail, so it never reports
+    /// one.
+    Error(TransportError),
+    /// The transport was closed. No further events are delivered after it closes.
+    Closed,
+}
+
+/// A bidirectional, ordered channel of messages between a client and a server.
+///
+/// [`ClientTransport`] and [`ServerTransport`] run a connection over any transport of
+/// [`Frame`]s, encoding messages in the wire format the client chose. Transports are
+/// polled, which suits in-process channels and transports driven from a loop of their
+/// own. The WebSocket integrations (`aper-stateroom` and `aper-websocket-client`) are
+/// instead driven by their runtime's events, so they pass frames between the socket and
+/// the connection directly.
+pub trait Transport {
+    type Outbound;
+    type Inbound;
+
+    fn send(&self, message: &Self::Outbound) -> Result<(), TransportError>;
+
+    /// Returns the next event if one is ready, without blocking.
+    fn try_recv(&self) -> Option<TransportEvent<Self::Inbound>>;
+
+    /// Waits up to `timeout` for the next event.
+    fn recv_timeout(&self, timeout: Duration) -> Option<TransportEvent<Self::Inbound>>;
+
+    fn close(&self);
+}
+
+/// An in-process [`Transport`], created in connected pairs by [`channel_transport`].
+pub struct ChannelTransport<Outbound, Inbound> {
+    sender: Mutex<Option<Sender<Outbound>>>,
+    receiver: Mutex<Receiver<Inbound>>,
+}
+
+/// Creates a pair of connected in-process transports, e.g. for a client and a server
+/// in the same process.
+pub fn channel_transport<A, B>() -> (ChannelTransport<A, B>, ChannelTransport<B, A>) {
+    let (a_sender, a_receiver) = channel();
+    let (b_sender, b_receiver) = channel();
+
+    (
+        ChannelTransport {
+            sender: Mutex::new(Some(a_sender)),
+            receiver: Mutex::new(b_receiver),
+        },
+        ChannelTransport {
+            sender: Mutex::new(Some(b_sender)),
+            receiver: Mutex::new(a_receiver),
+        },
+    )
+}
+
+impl<Outbound: Clone, Inbound> Transport for ChannelTransport<Outbound, Inbound> {
+    type Outbound = Outbound;
+    type Inbound = Inbound;
+
+    fn send(&self, message: &Outbound) -> Result<(), TransportError> {
+        let sender = self.sender.lock().unwrap();
+        let Some(sender) = sender.as_ref() else {
+            return Err(TransportError::Closed);
+        };
+
+        sender
+            .send(message.clone())
+            .map_err(|_| TransportError::Closed)
+    }
+
+    fn try_recv(&self) -> Option<TransportEvent<Inbound>> {
+        match self.receiver.lock().unwrap().try_recv() {
+            Ok(message) => Some(TransportEvent::Message(message)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(TransportEvent::Closed),
+        }
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<TransportEvent<Inbound>> {
+        match self.receiver.lock().unwrap().recv_timeout(timeout) {
+            Ok(message) => Some(TransportEvent::Message(message)),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => Some(TransportEvent::Closed),
+        }
+    }
+
+    fn close(&self) {
+        // Dropping the sender closes the channel for the other end once it has received
+        // everything already sent.
+        self.sender.lock().unwrap().take();
+    }
+}
+
+/// A [`ClientConnection`] whose messages are carried by a [`Transport`].
+pub struct ClientTransport<A: Aper, T> {
+    connection: ClientConnection<A>,
+    transport: Arc<T>,
+}
+
+impl<A: Aper, T> ClientTransport<A, T>
+where
+    T: Transport<Outbound = Frame, Inbound = Frame> + 'static,
+{
+    pub fn new(client: AperClient<A>, transport: T) -> Self {
+        Self::with_wire_format(client, WireFormat::default(), transport)
+    }
+
+    /// Connect using `wire_format` to encode messages and intents.
+    pub fn with_wire_format(client: AperClient<A>, wire_format: WireFormat, transport: T) -> Self {
+        let transport = Arc::new(transport);
+        let connection = ClientConnection::with_wire_format(
+            client,
+            wire_format,
+            sender(transport.clone(), wire_format),
+        );
+
+        Self {
+            connection,
+            transport,
+        }
+    }
+
+    /// Resume the session over a new transport, after the previous one closed or failed.
+    /// See [`ClientConnection::reconnect`].
+    pub fn reconnect(&mut self, transport: T) {
+        self.transport = Arc::new(transport);
+        let sender = sender(self.transport.clone(), self.connection.wire_format());
+        self.connection.reconnect(sender);
+    }
+
+    pub fn connection(&self) -> &ClientConnection<A> {
+        &self.connection
+    }
+
+    pub fn connection_mut(&mut self) -> &mut ClientConnection<A> {
+        &mut self.connection
+    }
+
+    /// Applies every message that has arrived from the server, returning once none are
+    /// ready. Returns an error once the transport has closed or failed.
+    pub fn process(&mut self) -> Result<(), TransportError> {
+        while let Some(event) = self.transport.try_recv() {
+            match event {
+                TransportEvent::Message(frame) => {
+                    match MessageToClient::decode_frame(&frame, self.connection.wire_format()) {
+                        Ok(message) => self.connection.receive(&message),
+                        Err(err) => tracing::warn!(?err, "ignoring malformed message"),
+                    }
+                }
+                TransportEvent::Error(err) => return Err(err),
+                TransportEvent::Closed => return Err(TransportError::Closed),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.transport.close();
+    }
+}
+
+/// Returns a callback that encodes a client's messages in `wire_format` and sends them
+/// over `transport`.
+fn sender<T>(transport: Arc<T>, wire_format: WireFormat) -> impl Fn(MessageToServer)
+where
+    T: Transport<Outbound = Frame>,
+{
+    move |message| {
+        let frame = match message.encode_frame(wire_format) {
+            Ok(frame) => frame,
+            Err(err) => {
+                tracing::error!(?err, "failed to encode message; dropping it");
+                return;
+            }
+        };
+
+        if let Err(err) = transport.send(&frame) {
+            // The transport reports the failure to the next call to `process`.
+            tracing::warn!(?err, "failed to send message to server");
+        }
+    }
+}
+
+/// A client's connection to a [`ServerConnection`], carried by a [`Transport`].
+pub struct ServerTransport<A: Aper, T> {
+    handle: ServerHandle<A>,
+    transport: Arc<T>,
+}
+
+impl<A: Aper, T> ServerTransport<A, T>
+where
+    T: Transport<Outbound = Frame, Inbound = Frame> + Send + Sync + 'static,
+{
+    /// Connects a client to `server`. Messages to it are encoded in the wire format it
+    /// chose in its handshake.
+    pub fn new(server: &mut ServerConnection<A>, transport: T) -> Self {
+        let transport = Arc::new(transport);
+
+        let transport_ = transport.clone();
+        let handle = server.connect_framed(move |frame| {
+            if let Err(err) = transport_.send(&frame) {
+                tracing::warn!(?err, "failed to send message to client");
+            }
+        });
+
+        Self { handle, transport }
+    }
+
+    pub fn handle(&self) -> &ServerHandle<A> {
+        &self.handle
+    }
+
+    pub fn handle_mut(&mut self) -> &mut ServerHandle<A> {
+        &mut self.handle
+    }
+
+    /// Handles every message that has arrived from the client, returning once none are
+    /// ready. Returns an error once the transport has closed or failed, or once the client
+    /// has been disconnected for a protocol error (in which case the transport is closed).
+    pub fn process(&mut self) -> Result<(), TransportError> {
+        while let Some(event) = self.transport.try_recv() {
+            match event {
+                TransportEvent::Message(frame) => {
+                    if let Err(err) = self.handle.receive_frame(&frame) {
+                        if self.handle.is_disconnected() {
+                            self.transport.close();
+                            return Err(TransportError::Failed(err.to_string()));
+                        }
+                    }
+                }
+                TransportEvent::Error(err) => return Err(err),
+                TransportEvent::Closed => return Err(TransportError::Closed),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.transport.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn channel_close_is_seen_after_pending_messages() {
+        let (a, b) = channel_transport::<u32, u32>();
+
+        a.send(&1).unwrap();
+        a.send(&2).unwrap();
+        a.close();
+
+        assert_eq!(a.send(&3), Err(TransportError::Closed));
+        assert_eq!(b.try_recv(), Some(TransportEvent::Message(1)));
+        assert_eq!(
+            b.recv_timeout(Duration::from_millis(10)),
+            Some(TransportEvent::Message(2))
+        );
+        assert_eq!(b.try_recv(), Some(TransportEvent::Closed));
+
+        // The other direction is still open until its sender closes.
+        b.send(&4).unwrap();
+        assert_eq!(a.try_recv(), Some(TransportEvent::Message(4)));
+        assert_eq!(a.try_recv(), None);
+    }
+}
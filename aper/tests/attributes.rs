@@ -0,0 +1,63 @@
+use aper::{
+    data_structures::{Atom, Switch},
+    AperSync, AperSyncEnum, Store,
+};
+
+#[derive(AperSync, Clone)]
+struct SettingsV1 {
+    volume: Atom<u8>,
+    theme: Atom<String>,
+}
+
+#[derive(AperSync, Clone)]
+struct SettingsV2 {
+    #[aper(rename = "volume")]
+    master_volume: Atom<u8>,
+    #[aper(key = b"theme")]
+    color_scheme: Atom<String>,
+    #[aper(skip)]
+    local_cache: Vec<String>,
+}
+
+#[derive(AperSync, Clone)]
+enum Mode {
+    #[aper(rename = "Edit")]
+    Editing,
+    Viewing,
+}
+
+#[test]
+fn renamed_fields_read_existing_data() {
+    let store = Store::default();
+    let mut v1 = SettingsV1::attach(store.handle());
+    v1.volume.set(11);
+    v1.theme.set("dark".to_string());
+
+    let v2 = SettingsV2::attach(store.handle());
+    assert_eq!(v2.master_volume.get(), 11);
+    assert_eq!(v2.color_scheme.get(), "dark");
+    assert!(v2.local_cache.is_empty());
+}
+
+#[test]
+fn skipped_fields_are_not_stored() {
+    let store = Store::default();
+    let mut settings = SettingsV2::attach(store.handle());
+    settings.master_volume.set(3);
+
+    assert_eq!(
+        store.prefixes(),
+        vec![vec![b"theme".to_vec()], vec![b"volume".to_vec()]]
+    );
+}
+
+#[test]
+fn renamed_variants() {
+    let store = Store::default();
+    let mut mode = Switch::<Mode>::attach(store.handle());
+
+    assert_eq!(Mode::variants(), &["Edit", "Viewing"]);
+    assert_eq!(mode.variant(), "Edit");
+    assert!(mode.set_variant("Editing").is_none());
+    assert!(matches!(mode.set_variant("Viewing"), Some(Mode::Viewing)));
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use aper::{
+    connection::{MessageToServer, ServerConnection},
+    data_structures::Atom,
+    Aper, AperSync, ClientIdentity, Denied, IntentMetadata,
+};
+use common::{Queue, TestClient};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(AperSync, Clone)]
+struct Game {
+    current_player: Atom<String>,
+    moves: Atom<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum GameIntent {
+    Move,
+    Reset,
+}
+
+impl Aper for Game {
+    type Intent = GameIntent;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &GameIntent, _metadata: &IntentMetadata) -> Result<(), ()> {
+        match intent {
+            GameIntent::Move => {
+                let next = if self.current_player.get() == "alice" {
+                    "bob"
+                } else {
+                    "alice"
+                };
+                self.current_player.set(next.to_string());
+                self.moves.set(self.moves.get() + 1);
+            }
+            GameIntent::Reset => {
+                self.current_player.set("alice".to_string());
+                self.moves.set(0);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn authorize(
+        &self,
+        intent: &GameIntent,
+        _metadata: &IntentMetadata,
+        identity: &ClientIdentity,
+    ) -> Result<(), Denied> {
+        match intent {
+            GameIntent::Move if identity.user.as_ref() != Some(&self.current_player.get()) => {
+                Err(Denied::new("It is not your turn."))
+            }
+            GameIntent::Reset if identity.claim("role") != Some("admin") => {
+                Err(Denied::new("Only admins can reset the game."))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Records the intents the server denies for `player`.
+fn record_denied(player: &mut TestClient<Game>) -> Queue<(GameIntent, Denied)> {
+    let denied: Queue<(GameIntent, Denied)> = Arc::default();
+    let queue = denied.clone();
+    player.connection.on_denied(move |intent, denied| {
+        queue.lock().unwrap().push((intent.clone(), denied.clone()))
+    });
+    denied
+}
+
+/// Reconnects `player`, asking to resume the session `client_id` with `resume_token`.
+fn resume_as(player: &mut TestClient<Game>, client_id: u32, resume_token: &str) {
+    player.reconnect();
+    for message in player.to_server.lock().unwrap().iter_mut() {
+        if let MessageToServer::Resume {
+            client_id: id,
+            resume_token: token,
+            ..
+        } = message
+        {
+            *id = Some(client_id);
+            *token = Some(resume_token.to_string());
+        }
+    }
+    player.deliver();
+}
+
+#[test]
+fn only_current_player_can_move() {
+    let mut server = ServerConnection::<Game>::new();
+    let mut alice = TestClient::connect_as(
+        &mut server,
+        ClientIdentity::user("alice").with_claim("role", "admin"),
+    );
+    let mut bob = TestClient::connect_as(&mut server, ClientIdentity::user("bob"));
+    let alice_denied = record_denied(&mut alice);
+    let bob_denied = record_denied(&mut bob);
+
+    alice.connection.apply(GameIntent::Reset).unwrap();
+    alice.deliver();
+    bob.deliver();
+
+    bob.connection.apply(GameIntent::Move).unwrap();
+    assert_eq!(bob.connection.state().moves.get(), 1);
+    bob.deliver();
+
+    // The move is undone, and Bob is told why.
+    assert_eq!(bob.connection.state().moves.get(), 0);
+    assert_eq!(
+        *bob_denied.lock().unwrap(),
+        vec![(GameIntent::Move, Denied::new("It is not your turn."))]
+    );
+
+    alice.connection.apply(GameIntent::Move).unwrap();
+    alice.deliver();
+    bob.connection.apply(GameIntent::Move).unwrap();
+    bob.deliver();
+    assert_eq!(server.state().moves.get(), 2);
+
+    bob.connection.apply(GameIntent::Reset).unwrap();
+    bob.deliver();
+    assert_eq!(server.state().moves.get(), 2);
+    assert_eq!(bob_denied.lock().unwrap().len(), 2);
+    assert!(alice_denied.lock().unwrap().is_empty());
+}
+
+#[test]
+fn session_can_only_be_resumed_by_same_identity() {
+    let mut server = ServerConnection::<Game>::new();
+    let alice = TestClient::connect_as(&mut server, ClientIdentity::user("alice"));
+    let alice_id = alice.id();
+    let alice_token = alice.connection.resume_token().unwrap().to_string();
+    drop(alice);
+
+    let mut mallory = TestClient::connect_as(&mut server, ClientIdentity::user("mallory"));
+    resume_as(&mut mallory, alice_id, &alice_token);
+    assert_ne!(mallory.connection.client_id(), Some(alice_id));
+
+    let mut alice = TestClient::connect_as(&mut server, ClientIdentity::user("alice"));
+    resume_as(&mut alice, alice_id, &alice_token);
+    assert_eq!(alice.connection.client_id(), Some(alice_id));
+}
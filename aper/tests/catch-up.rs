@@ -0,0 +1,116 @@
+use aper::{
+    connection::{ClientConnection, MessageToClient, MessageToClientType, ServerConnection},
+    data_structures::AtomMap,
+    Aper, AperClient, AperServer, AperSync, IntentMetadata,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+#[derive(AperSync, Clone)]
+struct Registers {
+    values: AtomMap<u32, u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct Set(u32, u32);
+
+impl Aper for Registers {
+    type Intent = Set;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &Set, _metadata: &IntentMetadata) -> Result<(), ()> {
+        self.values.set(&intent.0, &intent.1);
+        Ok(())
+    }
+}
+
+#[test]
+fn mutations_since_is_bounded() {
+    let mut server = AperServer::<Registers>::new();
+    server.set_history_length(2);
+
+    for i in 0..4 {
+        server.apply(&Set(i, i), &IntentMetadata::now()).unwrap();
+    }
+
+    assert_eq!(server.version(), 4);
+    assert_eq!(server.mutations_since(4).unwrap().len(), 0);
+    assert_eq!(server.mutations_since(3).unwrap().len(), 1);
+    assert_eq!(server.mutations_since(2).unwrap().len(), 2);
+    assert!(server.mutations_since(1).is_none());
+    assert!(server.mutations_since(5).is_none());
+}
+
+#[test]
+fn reconnecting_client_receives_only_missing_mutations() {
+    let mut server = ServerConnection::<Registers>::new();
+
+    // A client that has seen everything up to version 3.
+    let mut client = AperClient::<Registers>::new();
+    for i in 0..3 {
+        let mutations = {
+            let mut standalone = AperServer::<Registers>::new();
+            standalone
+                .apply(&Set(i, i), &IntentMetadata::now())
+                .unwrap()
+        };
+        client.mutate(&mutations, None, i as u64 + 1);
+    }
+
+    let writer_inbox = Arc::new(Mutex::new(Vec::<MessageToClient>::new()));
+    let mut writer = {
+        let inbox = writer_inbox.clone();
+        server.connect(move |message| inbox.lock().unwrap().push(message.clone()))
+    };
+    writer
+        .receive(&aper::connection::MessageToServer::handshake::<Registers>(
+            Default::default(),
+        ))
+        .unwrap();
+    for i in 0..5 {
+        writer
+            .receive(&aper::connection::MessageToServer::Intent {
+                intent: bincode::serialize(&Set(i, i * 10)).unwrap(),
+                client_version: i as u64 + 1,
+            })
+            .unwrap();
+    }
+
+    // The client reconnects, asking for changes since version 3.
+    let to_server = Arc::new(Mutex::new(Vec::new()));
+    let outbox = to_server.clone();
+    let mut connection =
+        ClientConnection::new(client, move |message| outbox.lock().unwrap().push(message));
+
+    let to_client = Arc::new(Mutex::new(Vec::<MessageToClient>::new()));
+    let mut handle = {
+        let inbox = to_client.clone();
+        server.connect(move |message| inbox.lock().unwrap().push(message.clone()))
+    };
+
+    for message in to_server.lock().unwrap().drain(..) {
+        handle.receive(&message).unwrap();
+    }
+
+    let messages: Vec<_> = to_client.lock().unwrap().drain(..).collect();
+    let MessageToClientType::Apply {
+        mutations,
+        server_version,
+        ..
+    } = &messages[1].message
+    else {
+        panic!("Expected an Apply message.");
+    };
+
+    assert_eq!(*server_version, 5);
+    assert_eq!(mutations.len(), 2);
+
+    for message in &messages {
+        connection.receive(message);
+    }
+
+    let values: Vec<_> = connection.state().values.iter().collect();
+    assert_eq!(values, vec![(0, 0), (1, 1), (2, 2), (3, 30), (4, 40)]);
+}
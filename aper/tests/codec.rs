@@ -0,0 +1,232 @@
+use aper::{
+    codec::{Frame, FramedMessage, WireFormat},
+    connection::{
+        ClientConnection, MessageToClient, MessageToServer, ServerConnection, ServerHandle,
+    },
+    data_structures::AtomMap,
+    Aper, AperClient, AperSync, IntentMetadata,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+#[derive(AperSync, Clone)]
+struct Scores {
+    scores: AtomMap<String, u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct SetScore(String, u32);
+
+impl Aper for Scores {
+    type Intent = SetScore;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &SetScore, _metadata: &IntentMetadata) -> Result<(), ()> {
+        self.scores.set(&intent.0, &intent.1);
+        Ok(())
+    }
+}
+
+type Frames = Arc<Mutex<Vec<Frame>>>;
+
+fn sorted_scores(state: &Scores) -> Vec<(String, u32)> {
+    let mut scores: Vec<_> = state.scores.iter().collect();
+    scores.sort();
+    scores
+}
+
+struct FramedClient {
+    connection: ClientConnection<Scores>,
+    handle: ServerHandle<Scores>,
+    to_server: Frames,
+    to_client: Frames,
+}
+
+impl FramedClient {
+    fn connect(server: &mut ServerConnection<Scores>, wire_format: WireFormat) -> Self {
+        let to_client = Frames::default();
+        let queue = to_client.clone();
+        let handle = server.connect_framed(move |frame| queue.lock().unwrap().push(frame));
+
+        let to_server = Frames::default();
+        let queue = to_server.clone();
+        let connection =
+            ClientConnection::with_wire_format(AperClient::new(), wire_format, move |message| {
+                // Only the handshake can be encoded if the wire format is unsupported.
+                if let Ok(frame) = message.encode_frame(wire_format) {
+                    queue.lock().unwrap().push(frame);
+                }
+            });
+
+        Self {
+            connection,
+            handle,
+            to_server,
+            to_client,
+        }
+    }
+
+    fn deliver(&mut self) {
+        let frames: Vec<_> = self.to_server.lock().unwrap().drain(..).collect();
+        for frame in frames {
+            self.handle.receive_frame(&frame).unwrap();
+        }
+
+        let frames: Vec<_> = self.to_client.lock().unwrap().drain(..).collect();
+        for frame in frames {
+            let message =
+                MessageToClient::decode_frame(&frame, self.connection.wire_format()).unwrap();
+            self.connection.receive(&message);
+        }
+    }
+}
+
+#[test]
+fn clients_with_different_wire_formats_share_state() {
+    let mut server = ServerConnection::<Scores>::new();
+    let mut rust_client = FramedClient::connect(&mut server, WireFormat::Bincode);
+    let mut json_client = FramedClient::connect(&mut server, WireFormat::Json);
+
+    rust_client.deliver();
+    json_client.deliver();
+    assert_eq!(json_client.handle.wire_format(), WireFormat::Json);
+
+    json_client
+        .connection
+        .apply(SetScore("alice".into(), 3))
+        .unwrap();
+    assert!(json_client
+        .to_server
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|frame| matches!(frame, Frame::Text(_))));
+    json_client.deliver();
+
+    rust_client
+        .connection
+        .apply(SetScore("bob".into(), 5))
+        .unwrap();
+    rust_client.deliver();
+    json_client.deliver();
+
+    let expected = vec![("alice".to_string(), 3), ("bob".to_string(), 5)];
+    assert_eq!(sorted_scores(&server.state()), expected);
+    assert_eq!(sorted_scores(&rust_client.connection.state()), expected);
+    assert_eq!(sorted_scores(&json_client.connection.state()), expected);
+}
+
+/// Connects a client using `wire_format` alongside a bincode client, and checks that
+/// intents from each reach the other.
+#[cfg(any(feature = "postcard", feature = "msgpack"))]
+fn assert_round_trip(wire_format: WireFormat) {
+    let mut server = ServerConnection::<Scores>::new();
+    let mut rust_client = FramedClient::connect(&mut server, WireFormat::Bincode);
+    let mut client = FramedClient::connect(&mut server, wire_format);
+
+    rust_client.deliver();
+    client.deliver();
+    assert_eq!(client.handle.wire_format(), wire_format);
+
+    client
+        .connection
+        .apply(SetScore("alice".into(), 3))
+        .unwrap();
+    client.deliver();
+
+    rust_client
+        .connection
+        .apply(SetScore("bob".into(), 5))
+        .unwrap();
+    rust_client.deliver();
+    client.deliver();
+
+    let expected = vec![("alice".to_string(), 3), ("bob".to_string(), 5)];
+    assert_eq!(sorted_scores(&server.state()), expected);
+    assert_eq!(sorted_scores(&rust_client.connection.state()), expected);
+    assert_eq!(sorted_scores(&client.connection.state()), expected);
+    assert_eq!(client.connection.pending_intents(), 0);
+}
+
+#[test]
+#[cfg(feature = "postcard")]
+fn postcard_clients_share_state() {
+    assert_round_trip(WireFormat::Postcard);
+}
+
+#[test]
+#[cfg(feature = "msgpack")]
+fn msgpack_clients_share_state() {
+    assert_round_trip(WireFormat::MessagePack);
+}
+
+#[test]
+#[cfg(not(feature = "postcard"))]
+fn unsupported_wire_format_is_rejected() {
+    use aper::connection::{HandshakeRejection, MessageToClientType};
+
+    let mut server = ServerConnection::<Scores>::new();
+    let client = FramedClient::connect(&mut server, WireFormat::Postcard);
+
+    let mut handle = client.handle;
+    let handshake = client.to_server.lock().unwrap()[0].clone();
+    handle.receive_frame(&handshake).unwrap();
+
+    // The rejection is sent as JSON, since no wire format was agreed.
+    let frame = client.to_client.lock().unwrap()[0].clone();
+    let message = MessageToClient::decode_frame(&frame, WireFormat::Postcard).unwrap();
+    assert!(matches!(
+        message.message,
+        MessageToClientType::HandshakeRejected {
+            reason: HandshakeRejection::UnsupportedWireFormat(WireFormat::Postcard)
+        }
+    ));
+}
+
+#[derive(AperSync, Clone)]
+struct Board {
+    cells: AtomMap<(u32, u32), char>,
+}
+
+/// Sets cells keyed by their (row, column), which JSON can't encode as map keys.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct SetCells(BTreeMap<(u32, u32), char>);
+
+impl Aper for Board {
+    type Intent = SetCells;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &SetCells, _metadata: &IntentMetadata) -> Result<(), ()> {
+        for (cell, value) in &intent.0 {
+            self.cells.set(cell, value);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn intent_that_cannot_be_encoded_is_dropped() {
+    let to_server: Arc<Mutex<Vec<MessageToServer>>> = Arc::default();
+    let queue = to_server.clone();
+    let mut client = ClientConnection::with_wire_format(
+        AperClient::<Board>::new(),
+        WireFormat::Json,
+        move |message| queue.lock().unwrap().push(message),
+    );
+    let sent = to_server.lock().unwrap().len();
+
+    client
+        .apply(SetCells(BTreeMap::from([((0, 0), 'x')])))
+        .unwrap();
+
+    assert!(client.state().cells.get(&(0, 0)).is_none());
+    assert_eq!(client.pending_intents(), 0);
+    assert_eq!(to_server.lock().unwrap().len(), sent);
+}
@@ -0,0 +1,108 @@
+//! An in-process client and server connection, shared by the connection tests.
+
+// Each test binary only uses some of the helpers.
+#![allow(dead_code)]
+
+use aper::{
+    connection::{
+        ClientConnection, MessageToClient, MessageToServer, ServerConnection, ServerHandle,
+    },
+    Aper, AperClient, ClientIdentity,
+};
+use std::sync::{Arc, Mutex};
+
+pub type Queue<T> = Arc<Mutex<Vec<T>>>;
+
+/// A client connected to a server in the same process. Messages in each direction are
+/// queued until [`TestClient::deliver`] is called.
+pub struct TestClient<A: Aper> {
+    pub connection: ClientConnection<A>,
+    pub handle: ServerHandle<A>,
+    pub to_server: Queue<MessageToServer>,
+    pub to_client: Queue<MessageToClient>,
+    /// Every message that has been delivered to the client.
+    pub received: Queue<MessageToClient>,
+}
+
+impl<A: Aper> TestClient<A> {
+    /// Connects an anonymous client, and delivers its handshake.
+    pub fn connect(server: &mut ServerConnection<A>) -> Self {
+        Self::connect_as(server, ClientIdentity::default())
+    }
+
+    /// Connects a client with `identity`, and delivers its handshake.
+    pub fn connect_as(server: &mut ServerConnection<A>, identity: ClientIdentity) -> Self {
+        let (mut handle, to_client) = connect_handle(server);
+        handle.set_identity(identity);
+
+        let to_server: Queue<MessageToServer> = Arc::default();
+        let connection = ClientConnection::new(AperClient::new(), sender(&to_server));
+
+        let mut client = TestClient {
+            connection,
+            handle,
+            to_server,
+            to_client,
+            received: Arc::default(),
+        };
+        client.deliver();
+        client
+    }
+
+    /// Resumes the session over the same server handle, as if the connection had dropped.
+    /// The resume request is queued, not delivered.
+    pub fn reconnect(&mut self) {
+        self.connection.reconnect(sender(&self.to_server));
+    }
+
+    /// Drops the connection to the server, and resumes the session over a new one. The
+    /// resume request is queued, not delivered.
+    pub fn reconnect_to(&mut self, server: &mut ServerConnection<A>) {
+        let (handle, to_client) = connect_handle(server);
+        self.handle = handle;
+        self.to_client = to_client;
+        self.connection.reconnect(sender(&self.to_server));
+    }
+
+    /// Delivers the messages queued for the server, then those queued for the client.
+    pub fn deliver(&mut self) {
+        self.deliver_to_server();
+        self.deliver_to_client();
+    }
+
+    pub fn deliver_to_server(&mut self) {
+        let messages: Vec<_> = self.to_server.lock().unwrap().drain(..).collect();
+        for message in messages {
+            self.handle.receive(&message).unwrap();
+        }
+    }
+
+    pub fn deliver_to_client(&mut self) {
+        let messages: Vec<_> = self.to_client.lock().unwrap().drain(..).collect();
+        for message in messages {
+            self.connection.receive(&message);
+            self.received.lock().unwrap().push(message);
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.connection.client_id().unwrap()
+    }
+}
+
+/// Connects to the server without a client, returning the handle and the queue of messages
+/// sent to the client over it.
+pub fn connect_handle<A: Aper>(
+    server: &mut ServerConnection<A>,
+) -> (ServerHandle<A>, Queue<MessageToClient>) {
+    let to_client: Queue<MessageToClient> = Arc::default();
+    let queue = to_client.clone();
+    let handle = server.connect(move |message| queue.lock().unwrap().push(message.clone()));
+    (handle, to_client)
+}
+
+/// A client callback that queues the messages it is given.
+pub fn sender(queue: &Queue<MessageToServer>) -> impl Fn(MessageToServer) + 'static {
+    let queue = queue.clone();
+    move |message| queue.lock().unwrap().push(message)
+}
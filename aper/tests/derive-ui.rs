@@ -0,0 +1,7 @@
+//! Checks the compile errors produced by `#[derive(AperSync)]` when it is misused.
+
+#[test]
+fn derive_ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,110 @@
+use aper::{
+    data_structures::{Atom, Switch},
+    Aper, AperSync, AperSyncEnum, IntentMetadata, Store,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(AperSync, Clone)]
+struct LobbyState {
+    players: Atom<u32>,
+}
+
+#[derive(AperSync, Clone)]
+struct GameState {
+    turn: Atom<u32>,
+}
+
+#[derive(AperSync, Clone)]
+enum Phase {
+    Lobby(LobbyState),
+    Playing { game: GameState },
+    Ended,
+}
+
+#[derive(AperSync, Clone)]
+struct Room {
+    phase: Switch<Phase>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum RoomIntent {
+    Join,
+    Start,
+    Move,
+    End,
+}
+
+impl Aper for Room {
+    type Intent = RoomIntent;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(
+        &mut self,
+        intent: &Self::Intent,
+        _metadata: &IntentMetadata,
+    ) -> Result<(), Self::Error> {
+        match (intent, self.phase.get()) {
+            (RoomIntent::Join, Phase::Lobby(mut lobby)) => {
+                lobby.players.set(lobby.players.get() + 1);
+            }
+            (RoomIntent::Start, Phase::Lobby(_)) => {
+                self.phase.set_variant("Playing").ok_or(())?;
+            }
+            (RoomIntent::Move, Phase::Playing { mut game }) => {
+                game.turn.set(game.turn.get() + 1);
+            }
+            (RoomIntent::End, Phase::Playing { .. }) => {
+                self.phase.set_variant("Ended").ok_or(())?;
+            }
+            _ => return Err(()),
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn enum_defaults_to_first_variant() {
+    let store = Store::default();
+    let room = Room::attach(store.handle());
+
+    assert!(matches!(room.phase.get(), Phase::Lobby(_)));
+    assert_eq!(room.phase.variant(), "Lobby");
+    assert_eq!(Phase::variants(), &["Lobby", "Playing", "Ended"]);
+}
+
+#[test]
+fn switching_variants_clears_old_data() {
+    let store = Store::default();
+    let mut room = Room::attach(store.handle());
+    let metadata = IntentMetadata::now();
+
+    room.apply(&RoomIntent::Join, &metadata).unwrap();
+    room.apply(&RoomIntent::Join, &metadata).unwrap();
+
+    let Phase::Lobby(lobby) = room.phase.get() else {
+        panic!("Expected lobby.");
+    };
+    assert_eq!(lobby.players.get(), 2);
+
+    room.apply(&RoomIntent::Start, &metadata).unwrap();
+    room.apply(&RoomIntent::Move, &metadata).unwrap();
+    assert!(room.apply(&RoomIntent::Join, &metadata).is_err());
+
+    let Phase::Playing { game } = room.phase.get() else {
+        panic!("Expected game.");
+    };
+    assert_eq!(game.turn.get(), 1);
+
+    // The lobby's data was deleted when the game started.
+    assert!(!store
+        .prefixes()
+        .iter()
+        .any(|prefix| prefix.len() > 1 && prefix[1].as_ref() == b"Lobby"));
+
+    room.apply(&RoomIntent::End, &metadata).unwrap();
+    assert!(matches!(room.phase.get(), Phase::Ended));
+    assert!(room.phase.set_variant("Unknown").is_none());
+}
@@ -0,0 +1,72 @@
+use aper::{
+    data_structures::{Atom, FixedArray, Map},
+    AperSync, Store,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+#[derive(AperSync, Clone)]
+struct Pair<T: Serialize + DeserializeOwned + Default> {
+    a: Atom<T>,
+    b: Atom<T>,
+}
+
+#[derive(AperSync, Clone)]
+struct Inventory<Item>
+where
+    Item: AperSync,
+{
+    items: Map<String, Item>,
+}
+
+#[derive(AperSync, Clone)]
+struct Board<const N: u32> {
+    cells: FixedArray<N, u8>,
+}
+
+#[derive(AperSync, Clone)]
+enum Slot<T: AperSync> {
+    Empty,
+    Full(T),
+}
+
+#[test]
+fn generic_struct() {
+    let store = Store::default();
+    let mut pair = Pair::<String>::attach(store.handle());
+
+    pair.a.set("left".to_string());
+    pair.b.set("right".to_string());
+
+    let pair = Pair::<String>::attach(store.handle());
+    assert_eq!(pair.a.get(), "left");
+    assert_eq!(pair.b.get(), "right");
+}
+
+#[test]
+fn nested_generic_struct() {
+    let store = Store::default();
+    let mut inventory = Inventory::<Pair<u32>>::attach(store.handle());
+
+    let mut sword = inventory.items.get_or_create(&"sword".to_string());
+    sword.a.set(3);
+
+    let sword = inventory.items.get(&"sword".to_string()).unwrap();
+    assert_eq!(sword.a.get(), 3);
+}
+
+#[test]
+fn const_generic_struct() {
+    let store = Store::default();
+    let mut board = Board::<3>::attach(store.handle());
+
+    board.cells.set(2, 7);
+    assert_eq!(board.cells.iter().collect::<Vec<_>>(), vec![0, 0, 7]);
+}
+
+#[test]
+fn generic_enum() {
+    let store = Store::default();
+    let slot = Slot::<Atom<u8>>::attach(store.handle());
+
+    assert!(matches!(slot, Slot::Empty));
+}
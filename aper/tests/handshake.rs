@@ -0,0 +1,116 @@
+mod common;
+
+use aper::{
+    connection::{
+        ClientConnection, HandshakeRejection, MessageToClientType, MessageToServer, ProtocolError,
+        ServerConnection, PROTOCOL_VERSION,
+    },
+    data_structures::Atom,
+    Aper, AperClient, AperSync, IntentMetadata,
+};
+use common::{connect_handle, sender, Queue, TestClient};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(AperSync, Clone)]
+struct Counter {
+    value: Atom<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct Add(u32);
+
+impl Aper for Counter {
+    type Intent = Add;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 2;
+
+    fn apply(&mut self, intent: &Add, _metadata: &IntentMetadata) -> Result<(), ()> {
+        self.value.set(self.value.get() + intent.0);
+        Ok(())
+    }
+}
+
+/// The same state as `Counter`, as built into a client from before its intents changed.
+#[derive(AperSync, Clone)]
+struct OldCounter {
+    value: Atom<u32>,
+}
+
+impl Aper for OldCounter {
+    type Intent = Add;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &Add, _metadata: &IntentMetadata) -> Result<(), ()> {
+        self.value.set(self.value.get() + intent.0);
+        Ok(())
+    }
+}
+
+#[test]
+fn client_with_matching_schema_is_accepted() {
+    let mut server = ServerConnection::<Counter>::new();
+    let mut client = TestClient::connect(&mut server);
+
+    client.connection.apply(Add(2)).unwrap();
+    client.deliver();
+
+    assert_eq!(client.handle.protocol_version(), Some(PROTOCOL_VERSION));
+    assert_eq!(client.connection.protocol_version(), Some(PROTOCOL_VERSION));
+    assert_eq!(client.connection.handshake_rejection(), None);
+    assert_eq!(server.state().value.get(), 2);
+}
+
+#[test]
+fn client_with_different_schema_must_upgrade() {
+    let mut server = ServerConnection::<Counter>::new();
+    let (mut handle, to_client) = connect_handle(&mut server);
+
+    // The client is built with `OldCounter`, so it can't use `TestClient`.
+    let to_server: Queue<MessageToServer> = Arc::default();
+    let mut client = ClientConnection::new(AperClient::<OldCounter>::new(), sender(&to_server));
+
+    client.apply(Add(2)).unwrap();
+    let messages: Vec<_> = to_server.lock().unwrap().drain(..).collect();
+    handle.receive(&messages[0]).unwrap();
+
+    // The rest are ignored, since the handshake was rejected.
+    for message in &messages[1..] {
+        assert_eq!(
+            handle.receive(message),
+            Err(ProtocolError::HandshakeRequired)
+        );
+    }
+
+    let messages: Vec<_> = to_client.lock().unwrap().drain(..).collect();
+    client.receive(&messages[0]);
+
+    let rejection = client.handshake_rejection().unwrap();
+    assert_eq!(rejection, &HandshakeRejection::SchemaMismatch);
+    assert!(rejection.requires_upgrade());
+    assert_eq!(client.client_id(), None);
+    assert_eq!(handle.protocol_version(), None);
+    assert_eq!(server.state().value.get(), 0);
+}
+
+#[test]
+fn client_from_before_versioning_is_rejected() {
+    let mut server = ServerConnection::<Counter>::new();
+    let (mut handle, to_client) = connect_handle(&mut server);
+
+    // Older clients sent only the wire format.
+    let handshake: MessageToServer =
+        serde_json::from_str(r#"{"Handshake": {"wire_format": "Bincode"}}"#).unwrap();
+    handle.receive(&handshake).unwrap();
+
+    let message = to_client.lock().unwrap()[0].clone();
+    assert!(matches!(
+        message.message,
+        MessageToClientType::HandshakeRejected {
+            reason: HandshakeRejection::UnsupportedProtocolVersion { .. }
+        }
+    ));
+}
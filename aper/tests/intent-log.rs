@@ -0,0 +1,212 @@
+use aper::{
+    data_structures::Atom,
+    intent_log::{replay, FileIntentLog, IntentLog, LoggedIntent, ReplayError},
+    Aper, AperServer, AperSync, IntentError, IntentMetadata, ServerSnapshot,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+#[derive(AperSync, Clone)]
+struct Counter {
+    value: Atom<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum CounterIntent {
+    Add(u64),
+    Subtract(u64),
+}
+
+impl Aper for Counter {
+    type Intent = CounterIntent;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(
+        &mut self,
+        intent: &Self::Intent,
+        _metadata: &IntentMetadata,
+    ) -> Result<(), Self::Error> {
+        match intent {
+            CounterIntent::Add(amount) => self.value.set(self.value.get() + amount),
+            CounterIntent::Subtract(amount) => {
+                let value = self.value.get().checked_sub(*amount).ok_or(())?;
+                self.value.set(value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+struct MemoryLog(Arc<Mutex<Vec<LoggedIntent<CounterIntent>>>>);
+
+impl IntentLog<CounterIntent> for MemoryLog {
+    fn append(&mut self, entry: &LoggedIntent<CounterIntent>) -> std::io::Result<()> {
+        self.0.lock().unwrap().push(entry.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn only_accepted_intents_are_logged() {
+    let log = MemoryLog::default();
+    let mut server = AperServer::<Counter>::new();
+    server.set_intent_log(log.clone());
+
+    server
+        .apply(&CounterIntent::Add(3), &IntentMetadata::now())
+        .unwrap();
+    assert!(server
+        .apply(&CounterIntent::Subtract(5), &IntentMetadata::now())
+        .is_err());
+    server
+        .apply(&CounterIntent::Subtract(1), &IntentMetadata::now())
+        .unwrap();
+
+    let entries = log.0.lock().unwrap();
+    let logged: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.intent.clone(), entry.version))
+        .collect();
+    assert_eq!(
+        logged,
+        vec![(CounterIntent::Add(3), 1), (CounterIntent::Subtract(1), 2)]
+    );
+}
+
+#[test]
+fn replay_from_snapshot_and_file_log() {
+    let path = std::env::temp_dir().join(format!("aper-intent-log-{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut server = AperServer::<Counter>::new();
+    server.set_intent_log(FileIntentLog::open(&path).unwrap());
+
+    server
+        .apply(&CounterIntent::Add(10), &IntentMetadata::now())
+        .unwrap();
+    let snapshot = server.snapshot();
+    server
+        .apply(&CounterIntent::Add(5), &IntentMetadata::now())
+        .unwrap();
+    server
+        .apply(&CounterIntent::Subtract(2), &IntentMetadata::now())
+        .unwrap();
+    drop(server);
+
+    let entries = FileIntentLog::<CounterIntent>::read(&path).unwrap();
+    assert_eq!(entries.len(), 3);
+
+    // The whole log can be replayed from an empty snapshot...
+    let from_scratch = replay::<Counter>(&ServerSnapshot::default(), entries.clone()).unwrap();
+    assert_eq!(from_scratch.version(), 3);
+    assert_eq!(from_scratch.state().value.get(), 13);
+
+    // ...or just the tail from a snapshot taken part of the way through.
+    let restored = replay::<Counter>(&snapshot, entries).unwrap();
+    assert_eq!(restored.version(), 3);
+    assert_eq!(restored.state().value.get(), 13);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn log_is_appendable_after_a_torn_write() {
+    let path = std::env::temp_dir().join(format!("aper-torn-log-{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut server = AperServer::<Counter>::new();
+    server.set_intent_log(FileIntentLog::open(&path).unwrap());
+    server
+        .apply(&CounterIntent::Add(1), &IntentMetadata::now())
+        .unwrap();
+    drop(server);
+
+    // The server crashes part of the way through writing the next entry.
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(&[40, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]).unwrap();
+    drop(file);
+
+    let entries = FileIntentLog::<CounterIntent>::read(&path).unwrap();
+    let mut server = replay::<Counter>(&ServerSnapshot::default(), entries).unwrap();
+    server.set_intent_log(FileIntentLog::open(&path).unwrap());
+    server
+        .apply(&CounterIntent::Add(2), &IntentMetadata::now())
+        .unwrap();
+    server
+        .apply(&CounterIntent::Add(3), &IntentMetadata::now())
+        .unwrap();
+    drop(server);
+
+    let entries = FileIntentLog::<CounterIntent>::read(&path).unwrap();
+    let logged: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.intent.clone(), entry.version))
+        .collect();
+    assert_eq!(
+        logged,
+        vec![
+            (CounterIntent::Add(1), 1),
+            (CounterIntent::Add(2), 2),
+            (CounterIntent::Add(3), 3)
+        ]
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+struct FailingLog;
+
+impl IntentLog<CounterIntent> for FailingLog {
+    fn append(&mut self, _entry: &LoggedIntent<CounterIntent>) -> std::io::Result<()> {
+        Err(std::io::Error::other("disk full"))
+    }
+}
+
+#[test]
+fn intent_is_not_applied_if_it_cannot_be_logged() {
+    let mut server = AperServer::<Counter>::new();
+    server
+        .apply(&CounterIntent::Add(1), &IntentMetadata::now())
+        .unwrap();
+    server.set_intent_log(FailingLog);
+
+    let result = server.apply(&CounterIntent::Add(2), &IntentMetadata::now());
+    assert!(matches!(result, Err(IntentError::NotLogged(_))));
+    assert_eq!(server.version(), 1);
+    assert_eq!(server.state().value.get(), 1);
+}
+
+#[test]
+fn replay_detects_gaps() {
+    let entries = vec![
+        LoggedIntent {
+            intent: CounterIntent::Add(1),
+            metadata: IntentMetadata::now(),
+            version: 1,
+        },
+        LoggedIntent {
+            intent: CounterIntent::Add(1),
+            metadata: IntentMetadata::now(),
+            version: 3,
+        },
+    ];
+
+    let result = replay::<Counter>(&ServerSnapshot::default(), entries);
+    assert_eq!(
+        result.err(),
+        Some(ReplayError::VersionMismatch {
+            expected: 3,
+            found: 2
+        })
+    );
+}
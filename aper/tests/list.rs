@@ -0,0 +1,119 @@
+use aper::{
+    data_structures::{Atom, List, ListItemId},
+    Aper, AperClient, AperServer, AperSync, IntentMetadata,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::channel;
+
+#[derive(AperSync, Clone)]
+struct Playlist {
+    songs: List<Atom<String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum PlaylistIntent {
+    Append(ListItemId, String),
+    InsertAfter(ListItemId, ListItemId, String),
+    MoveFirst(ListItemId),
+}
+
+impl Aper for Playlist {
+    type Intent = PlaylistIntent;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(
+        &mut self,
+        intent: &Self::Intent,
+        _metadata: &IntentMetadata,
+    ) -> Result<(), Self::Error> {
+        match intent {
+            PlaylistIntent::Append(id, name) => {
+                self.songs.insert_last(id).set(name.clone());
+            }
+            PlaylistIntent::InsertAfter(id, anchor, name) => {
+                self.songs
+                    .insert_after(id, anchor)
+                    .ok_or(())?
+                    .set(name.clone());
+            }
+            PlaylistIntent::MoveFirst(id) => {
+                if !self.songs.move_first(id) {
+                    return Err(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn names(playlist: &Playlist) -> Vec<String> {
+    playlist.songs.iter().map(|(_, song)| song.get()).collect()
+}
+
+#[test]
+fn speculative_insert_converges_with_server() {
+    let mut server = AperServer::<Playlist>::new();
+    let mut client = AperClient::<Playlist>::new();
+
+    let mutations = server
+        .apply(
+            &PlaylistIntent::Append(ListItemId(1), "a".to_string()),
+            &IntentMetadata::now(),
+        )
+        .unwrap();
+    client.mutate(&mutations, None, server.version());
+
+    // The client speculatively inserts after "a"...
+    let local_intent = PlaylistIntent::InsertAfter(ListItemId(2), ListItemId(1), "b".to_string());
+    let version = client.apply(&local_intent, &IntentMetadata::now()).unwrap();
+    assert_eq!(names(&client.state()), vec!["a", "b"]);
+
+    // ...while another client's insert at the same place reaches the server first.
+    let remote_intent = PlaylistIntent::InsertAfter(ListItemId(3), ListItemId(1), "c".to_string());
+    let mutations = server
+        .apply(&remote_intent, &IntentMetadata::now())
+        .unwrap();
+    client.mutate(&mutations, None, server.version());
+
+    let speculative = names(&client.state());
+
+    let mutations = server.apply(&local_intent, &IntentMetadata::now()).unwrap();
+    client.mutate(&mutations, Some(version), server.version());
+
+    assert_eq!(names(&client.state()), names(&server.state()));
+    assert_eq!(speculative, names(&server.state()));
+}
+
+#[test]
+fn listener_is_alerted_on_reorder() {
+    let mut client = AperClient::<Playlist>::new();
+
+    client
+        .apply(
+            &PlaylistIntent::Append(ListItemId(1), "a".to_string()),
+            &IntentMetadata::now(),
+        )
+        .unwrap();
+    client
+        .apply(
+            &PlaylistIntent::Append(ListItemId(2), "b".to_string()),
+            &IntentMetadata::now(),
+        )
+        .unwrap();
+
+    let (send, recv) = channel();
+    client.state().songs.listen(move || send.send(()).is_ok());
+
+    client
+        .apply(
+            &PlaylistIntent::MoveFirst(ListItemId(2)),
+            &IntentMetadata::now(),
+        )
+        .unwrap();
+
+    assert!(recv.try_recv().is_ok());
+    assert_eq!(names(&client.state()), vec!["b", "a"]);
+}
@@ -24,6 +24,8 @@ impl Aper for SimpleStruct {
     type Intent = SimpleIntent;
     type Error = ();
 
+    const SCHEMA_VERSION: u32 = 1;
+
     fn apply(
         &mut self,
         intent: &Self::Intent,
@@ -153,6 +155,8 @@ impl Aper for LinkedFields {
     type Intent = LinkedFieldIntent;
     type Error = ();
 
+    const SCHEMA_VERSION: u32 = 1;
+
     fn apply(
         &mut self,
         intent: &Self::Intent,
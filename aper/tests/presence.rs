@@ -0,0 +1,111 @@
+mod common;
+
+use aper::{connection::ServerConnection, data_structures::Atom, Aper, AperSync, IntentMetadata};
+use common::TestClient;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(AperSync, Clone)]
+struct Document {
+    body: Atom<String>,
+}
+
+impl Aper for Document {
+    type Intent = String;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &String, _metadata: &IntentMetadata) -> Result<(), ()> {
+        self.body.set(intent.clone());
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct Cursor {
+    position: usize,
+}
+
+fn cursors(peer: &TestClient<Document>) -> BTreeMap<u32, Cursor> {
+    peer.connection.peer_presence()
+}
+
+#[test]
+fn presence_is_shared_and_cleared_on_disconnect() {
+    let mut server = ServerConnection::<Document>::new();
+    let mut alice = TestClient::connect(&mut server);
+    let mut bob = TestClient::connect(&mut server);
+
+    let changes = Arc::new(AtomicUsize::new(0));
+    let counter = changes.clone();
+    bob.connection.listen_presence(move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+        true
+    });
+
+    alice.connection.set_presence(&Cursor { position: 3 });
+    alice.deliver();
+    bob.deliver();
+
+    assert_eq!(
+        cursors(&bob),
+        BTreeMap::from([(alice.id(), Cursor { position: 3 })])
+    );
+    assert!(cursors(&alice).is_empty());
+    assert_eq!(changes.load(Ordering::SeqCst), 1);
+
+    // Presence doesn't touch the versioned state.
+    assert_eq!(server.state().body.get(), "");
+    assert_eq!(alice.connection.pending_intents(), 0);
+
+    // A client that connects later is sent the existing presence.
+    let carol = TestClient::connect(&mut server);
+    assert_eq!(
+        cursors(&carol),
+        BTreeMap::from([(alice.id(), Cursor { position: 3 })])
+    );
+
+    drop(alice);
+    bob.deliver();
+    assert!(cursors(&bob).is_empty());
+    assert_eq!(changes.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn presence_updates_are_throttled() {
+    let mut server = ServerConnection::<Document>::new();
+    server.set_presence_interval(Duration::from_millis(50));
+    let mut alice = TestClient::connect(&mut server);
+    let mut bob = TestClient::connect(&mut server);
+
+    for position in 0..5 {
+        alice.connection.set_presence(&Cursor { position });
+        alice.deliver();
+    }
+    bob.deliver();
+
+    // Only the first update was sent; the rest are coalesced.
+    assert_eq!(
+        cursors(&bob),
+        BTreeMap::from([(alice.id(), Cursor { position: 0 })])
+    );
+
+    let flush_at = alice.handle.next_presence_flush().unwrap();
+    std::thread::sleep(flush_at.saturating_duration_since(Instant::now()));
+    alice.handle.flush_presence();
+    assert_eq!(alice.handle.next_presence_flush(), None);
+    bob.deliver();
+
+    assert_eq!(
+        cursors(&bob),
+        BTreeMap::from([(alice.id(), Cursor { position: 4 })])
+    );
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use aper::{
+    codec::Frame,
+    connection::{
+        MessageToClient, MessageToClientType, MessageToServer, ProtocolError, ServerConnection,
+        ServerHandle,
+    },
+    data_structures::Atom,
+    Aper, AperSync, IntentMetadata,
+};
+use common::{connect_handle, Queue, TestClient};
+use serde::{Deserialize, Serialize};
+
+#[derive(AperSync, Clone)]
+struct Counter {
+    value: Atom<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct Add(u32);
+
+impl Aper for Counter {
+    type Intent = Add;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &Add, _metadata: &IntentMetadata) -> Result<(), ()> {
+        self.value.set(self.value.get() + intent.0);
+        Ok(())
+    }
+}
+
+/// Connects a client that has completed its handshake, returning its handle and the
+/// queue of messages sent to it.
+fn connect(
+    server: &mut ServerConnection<Counter>,
+) -> (ServerHandle<Counter>, Queue<MessageToClient>) {
+    let (mut handle, to_client) = connect_handle(server);
+
+    handle
+        .receive(&MessageToServer::handshake::<Counter>(Default::default()))
+        .unwrap();
+    to_client.lock().unwrap().clear();
+
+    (handle, to_client)
+}
+
+fn last_error(queue: &Queue<MessageToClient>) -> Option<ProtocolError> {
+    match &queue.lock().unwrap().last()?.message {
+        MessageToClientType::ProtocolError { error } => Some(error.clone()),
+        _ => None,
+    }
+}
+
+#[test]
+fn malformed_messages_are_reported_to_sender() {
+    let mut server = ServerConnection::<Counter>::new();
+    let (mut mallory, to_mallory) = connect(&mut server);
+    let mut alice = TestClient::connect(&mut server);
+
+    let result = mallory.receive_frame(&Frame::Binary(vec![0xff; 3]));
+    assert!(matches!(result, Err(ProtocolError::MalformedMessage(_))));
+    assert!(matches!(
+        last_error(&to_mallory),
+        Some(ProtocolError::MalformedMessage(_))
+    ));
+
+    let result = mallory.receive(&MessageToServer::Intent {
+        intent: vec![1],
+        client_version: 1,
+    });
+    assert!(matches!(result, Err(ProtocolError::MalformedIntent(_))));
+    assert!(!mallory.is_disconnected());
+
+    // The room is unaffected.
+    alice.connection.apply(Add(3)).unwrap();
+    alice.deliver();
+
+    assert_eq!(server.state().value.get(), 3);
+    assert_eq!(last_error(&alice.received), None);
+    assert_eq!(to_mallory.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn client_can_be_disconnected_on_protocol_error() {
+    let mut server = ServerConnection::<Counter>::new();
+    server.set_disconnect_on_protocol_error(true);
+    let (mut mallory, to_mallory) = connect(&mut server);
+    let (mut alice, _) = connect(&mut server);
+
+    let result = mallory.receive_frame(&Frame::Text("not a message".into()));
+    assert!(matches!(result, Err(ProtocolError::MalformedMessage(_))));
+    assert!(mallory.is_disconnected());
+
+    let intent = MessageToServer::Intent {
+        intent: bincode::serialize(&Add(1)).unwrap(),
+        client_version: 1,
+    };
+    assert_eq!(mallory.receive(&intent), Err(ProtocolError::Disconnected));
+
+    // Mallory no longer receives updates from other clients.
+    alice.receive(&intent).unwrap();
+    assert_eq!(server.state().value.get(), 1);
+    assert_eq!(to_mallory.lock().unwrap().len(), 1);
+}
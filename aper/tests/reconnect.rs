@@ -0,0 +1,234 @@
+mod common;
+
+use aper::{
+    connection::{MessageToClientType, MessageToServer, ServerConnection},
+    data_structures::AtomMap,
+    Aper, AperServer, AperSync, IntentMetadata,
+};
+use common::{connect_handle, TestClient};
+use serde::{Deserialize, Serialize};
+
+#[derive(AperSync, Clone)]
+struct Counters {
+    counts: AtomMap<String, u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum CounterIntent {
+    Increment(String),
+    Remove(String),
+}
+
+impl Aper for Counters {
+    type Intent = CounterIntent;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &CounterIntent, _metadata: &IntentMetadata) -> Result<(), ()> {
+        match intent {
+            CounterIntent::Increment(name) => {
+                let count = self.counts.get(name).unwrap_or(0);
+                self.counts.set(name, &(count + 1));
+            }
+            CounterIntent::Remove(name) => self.counts.delete(name),
+        }
+
+        Ok(())
+    }
+}
+
+fn counts(state: &Counters) -> Vec<(String, u32)> {
+    state.counts.iter().collect()
+}
+
+/// Applies `intents` from another client.
+fn apply_from_other(server: &mut ServerConnection<Counters>, intents: &[CounterIntent]) {
+    let (mut other, _) = connect_handle(server);
+    other
+        .receive(&MessageToServer::handshake::<Counters>(Default::default()))
+        .unwrap();
+    for (i, intent) in intents.iter().enumerate() {
+        other
+            .receive(&MessageToServer::Intent {
+                intent: bincode::serialize(intent).unwrap(),
+                client_version: i as u64 + 1,
+            })
+            .unwrap();
+    }
+}
+
+#[test]
+fn unacknowledged_intents_are_resent_once() {
+    let mut server = ServerConnection::<Counters>::new();
+    let mut client = TestClient::connect(&mut server);
+    let client_id = client.id();
+
+    // Confirmed before the connection drops.
+    client
+        .connection
+        .apply(CounterIntent::Increment("a".into()))
+        .unwrap();
+    client.deliver();
+
+    // Reaches the server, but the confirmation is lost.
+    client
+        .connection
+        .apply(CounterIntent::Increment("b".into()))
+        .unwrap();
+    client.deliver_to_server();
+    client.to_client.lock().unwrap().clear();
+
+    // Never reaches the server.
+    client
+        .connection
+        .apply(CounterIntent::Increment("c".into()))
+        .unwrap();
+    client.to_server.lock().unwrap().clear();
+
+    // Another client makes a change while this one is disconnected.
+    apply_from_other(&mut server, &[CounterIntent::Increment("a".into())]);
+
+    client.reconnect_to(&mut server);
+
+    // Applied while the session is resuming, so it must be sent after "c".
+    client
+        .connection
+        .apply(CounterIntent::Increment("d".into()))
+        .unwrap();
+    // Only the handshake and the resume request have been sent.
+    assert_eq!(client.to_server.lock().unwrap().len(), 2);
+    assert_eq!(client.connection.held_intents(), 3);
+
+    client.deliver_to_server();
+    assert_eq!(client.handle.client_id(), client_id);
+
+    client.deliver_to_client();
+    assert_eq!(client.connection.client_id(), Some(client_id));
+    assert_eq!(client.connection.held_intents(), 0);
+    assert_eq!(client.connection.pending_intents(), 2);
+
+    let resent: Vec<_> = client
+        .to_server
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|message| match message {
+            MessageToServer::Intent { client_version, .. } => *client_version,
+            _ => panic!("Expected an intent."),
+        })
+        .collect();
+    assert_eq!(resent, vec![3, 4]);
+
+    client.deliver();
+
+    let expected = vec![
+        ("a".to_string(), 2),
+        ("b".to_string(), 1),
+        ("c".to_string(), 1),
+        ("d".to_string(), 1),
+    ];
+    assert_eq!(counts(&server.state()), expected);
+    assert_eq!(counts(&client.connection.state()), expected);
+    assert_eq!(client.connection.pending_intents(), 0);
+}
+
+#[test]
+fn stale_client_is_reset_from_snapshot() {
+    let mut server = AperServer::<Counters>::new();
+    server.set_history_length(0);
+    let mut server = ServerConnection::from_server(server);
+
+    let mut client = TestClient::connect(&mut server);
+    client
+        .connection
+        .apply(CounterIntent::Increment("a".into()))
+        .unwrap();
+    client.deliver();
+
+    apply_from_other(
+        &mut server,
+        &[
+            CounterIntent::Remove("a".into()),
+            CounterIntent::Increment("b".into()),
+        ],
+    );
+
+    client.reconnect_to(&mut server);
+    client.deliver_to_server();
+
+    assert!(matches!(
+        client.to_client.lock().unwrap()[1].message,
+        MessageToClientType::Resumed { snapshot: true, .. }
+    ));
+
+    client.deliver_to_client();
+
+    assert_eq!(
+        counts(&client.connection.state()),
+        vec![("b".to_string(), 1)]
+    );
+}
+
+#[test]
+fn session_cannot_be_taken_over_without_its_token() {
+    let mut server = ServerConnection::<Counters>::new();
+
+    let mut clients: Vec<_> = (0..2).map(|_| TestClient::connect(&mut server)).collect();
+    let ids: Vec<_> = clients.iter().map(TestClient::id).collect();
+
+    // Each (anonymous) client asks to resume the other's session, which is still
+    // connected, with its own token.
+    for (i, client) in clients.iter_mut().enumerate() {
+        client
+            .handle
+            .receive(&MessageToServer::Resume {
+                client_id: Some(ids[1 - i]),
+                resume_token: client.connection.resume_token().map(str::to_string),
+                latest_version: 0,
+                client_version: 0,
+            })
+            .unwrap();
+        assert_eq!(client.handle.client_id(), ids[i]);
+    }
+
+    for client in &clients {
+        client.to_client.lock().unwrap().clear();
+    }
+
+    // Both clients still receive updates, and each only receives acknowledgements of its
+    // own intents.
+    for client in clients.iter_mut() {
+        client
+            .connection
+            .apply(CounterIntent::Increment("a".into()))
+            .unwrap();
+        client.deliver_to_server();
+    }
+
+    for client in clients.iter_mut() {
+        let acks = client
+            .to_client
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| {
+                matches!(
+                    message.message,
+                    MessageToClientType::Apply {
+                        client_version: Some(_),
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(acks, 1);
+
+        client.deliver_to_client();
+        assert_eq!(
+            counts(&client.connection.state()),
+            vec![("a".to_string(), 2)]
+        );
+        assert_eq!(client.connection.pending_intents(), 0);
+    }
+}
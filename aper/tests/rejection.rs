@@ -0,0 +1,95 @@
+mod common;
+
+use aper::{
+    connection::{MessageToServer, ServerConnection},
+    data_structures::Atom,
+    Aper, AperSync, IntentMetadata,
+};
+use common::{Queue, TestClient};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(AperSync, Clone)]
+struct Account {
+    balance: Atom<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum AccountIntent {
+    Deposit(u32),
+    Withdraw(u32),
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+enum AccountError {
+    InsufficientFunds { balance: u32 },
+}
+
+impl Aper for Account {
+    type Intent = AccountIntent;
+    type Error = AccountError;
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(
+        &mut self,
+        intent: &AccountIntent,
+        _metadata: &IntentMetadata,
+    ) -> Result<(), AccountError> {
+        let balance = self.balance.get();
+        match intent {
+            AccountIntent::Deposit(amount) => self.balance.set(balance + amount),
+            AccountIntent::Withdraw(amount) => {
+                if *amount > balance {
+                    return Err(AccountError::InsufficientFunds { balance });
+                }
+                self.balance.set(balance - amount);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn rejected_intent_error_reaches_client() {
+    let mut server = ServerConnection::<Account>::new();
+    server.report_rejected_intents();
+    let mut alice = TestClient::connect(&mut server);
+
+    let rejected: Queue<(AccountIntent, AccountError)> = Arc::default();
+    let queue = rejected.clone();
+    alice
+        .connection
+        .on_rejected(move |intent, error| queue.lock().unwrap().push((intent.clone(), error)));
+
+    alice.connection.apply(AccountIntent::Deposit(5)).unwrap();
+    alice.deliver();
+
+    // Another client withdraws first, so Alice's withdrawal succeeds locally but is
+    // rejected by the server.
+    {
+        let mut bob = server.connect(|_| {});
+        bob.receive(&MessageToServer::handshake::<Account>(Default::default()))
+            .unwrap();
+        bob.receive(&MessageToServer::Intent {
+            intent: bincode::serialize(&AccountIntent::Withdraw(4)).unwrap(),
+            client_version: 1,
+        })
+        .unwrap();
+    }
+
+    alice.connection.apply(AccountIntent::Withdraw(3)).unwrap();
+    assert_eq!(alice.connection.state().balance.get(), 2);
+    alice.deliver();
+
+    assert_eq!(
+        *rejected.lock().unwrap(),
+        vec![(
+            AccountIntent::Withdraw(3),
+            AccountError::InsufficientFunds { balance: 1 }
+        )]
+    );
+    assert_eq!(alice.connection.state().balance.get(), 1);
+    assert_eq!(alice.connection.pending_intents(), 0);
+}
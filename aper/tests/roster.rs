@@ -0,0 +1,99 @@
+mod common;
+
+use aper::{
+    connection::{ClientInfo, ServerConnection},
+    data_structures::AtomMap,
+    Aper, AperSync, ClientIdentity, IntentMetadata,
+};
+use common::TestClient;
+use serde::{Deserialize, Serialize};
+
+#[derive(AperSync, Clone)]
+struct Lobby {
+    online: AtomMap<u32, String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum LobbyIntent {
+    Arrive(u32, String),
+    Depart(u32),
+}
+
+impl Aper for Lobby {
+    type Intent = LobbyIntent;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &LobbyIntent, metadata: &IntentMetadata) -> Result<(), ()> {
+        // Only the server may change who is online.
+        if metadata.client.is_some() {
+            return Err(());
+        }
+
+        match intent {
+            LobbyIntent::Arrive(client_id, user) => self.online.set(client_id, user),
+            LobbyIntent::Depart(client_id) => self.online.delete(client_id),
+        }
+
+        Ok(())
+    }
+
+    fn client_joined(&self, client: &ClientInfo) -> Option<LobbyIntent> {
+        let user = client.identity.user.clone()?;
+        Some(LobbyIntent::Arrive(client.client_id, user))
+    }
+
+    fn client_left(&self, client: &ClientInfo) -> Option<LobbyIntent> {
+        Some(LobbyIntent::Depart(client.client_id))
+    }
+}
+
+fn online(member: &TestClient<Lobby>) -> Vec<(u32, String)> {
+    member.connection.state().online.iter().collect()
+}
+
+#[test]
+fn roster_tracks_connected_clients() {
+    let mut server = ServerConnection::<Lobby>::new();
+    server.set_broadcast_roster(true);
+
+    let mut alice = TestClient::connect_as(
+        &mut server,
+        ClientIdentity::user("alice").with_claim("token", "secret"),
+    );
+    let mut bob = TestClient::connect_as(&mut server, ClientIdentity::user("bob"));
+    alice.deliver();
+
+    let alice_id = alice.id();
+    let bob_id = bob.id();
+
+    let clients = server.clients();
+    assert_eq!(
+        clients
+            .iter()
+            .map(|client| client.client_id)
+            .collect::<Vec<_>>(),
+        vec![alice_id, bob_id]
+    );
+    assert_eq!(clients[0].identity.claim("token"), Some("secret"));
+
+    // Clients see the roster, without other clients' claims.
+    let roster = alice.connection.roster();
+    assert_eq!(roster.len(), 2);
+    assert_eq!(roster[1].identity, ClientIdentity::user("bob"));
+    assert_eq!(roster[0].identity.claim("token"), None);
+
+    // The state reacted to both clients joining.
+    let expected = vec![(alice_id, "alice".to_string()), (bob_id, "bob".to_string())];
+    assert_eq!(online(&alice), expected);
+    assert_eq!(online(&bob), expected);
+
+    drop(alice);
+    bob.deliver();
+
+    assert_eq!(server.clients().len(), 1);
+    assert_eq!(bob.connection.roster().len(), 1);
+    assert_eq!(online(&bob), vec![(bob_id, "bob".to_string())]);
+    assert_eq!(server.state().online.get(&alice_id), None);
+}
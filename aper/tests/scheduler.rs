@@ -0,0 +1,259 @@
+use aper::{
+    connection::ServerConnection,
+    data_structures::{Atom, AtomMap},
+    scheduler::{Scheduler, VirtualClock, MAX_RUNS_AT_ONCE},
+    Aper, AperSync, IntentMetadata, Timestamp,
+};
+use chrono::{Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A game with a turn clock for each player, and a clock for the whole round.
+#[derive(AperSync, Clone)]
+struct Game {
+    turn_deadlines: AtomMap<String, Timestamp>,
+    round_deadline: Atom<Option<Timestamp>>,
+    log: Atom<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum GameIntent {
+    StartRound(Timestamp),
+    Pass(String),
+    TimeOut(String),
+    EndRound,
+}
+
+impl Aper for Game {
+    type Intent = GameIntent;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &GameIntent, metadata: &IntentMetadata) -> Result<(), ()> {
+        match intent {
+            GameIntent::StartRound(start) => {
+                self.turn_deadlines
+                    .set(&"alice".into(), &(*start + Duration::seconds(10)));
+                self.turn_deadlines
+                    .set(&"bob".into(), &(*start + Duration::seconds(15)));
+                self.round_deadline
+                    .set(Some(*start + Duration::seconds(60)));
+            }
+            GameIntent::Pass(player) => {
+                // Cancels the player's clock.
+                self.turn_deadlines.delete(player);
+            }
+            GameIntent::TimeOut(player) => {
+                // Replaces the player's clock, relative to when it ran out.
+                self.turn_deadlines
+                    .set(player, &(metadata.timestamp + Duration::seconds(20)));
+                self.record(format!("{} timed out", player), metadata);
+            }
+            GameIntent::EndRound => {
+                for (player, _) in self.turn_deadlines.iter() {
+                    self.turn_deadlines.delete(&player);
+                }
+                self.round_deadline.set(None);
+                self.record("round over".into(), metadata);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scheduled_events(&self) -> BTreeMap<String, (GameIntent, IntentMetadata)> {
+        let mut events: BTreeMap<_, _> = self
+            .turn_deadlines
+            .iter()
+            .map(|(player, deadline)| {
+                (
+                    format!("turn:{}", player),
+                    (
+                        GameIntent::TimeOut(player),
+                        IntentMetadata::new(None, deadline),
+                    ),
+                )
+            })
+            .collect();
+
+        if let Some(deadline) = self.round_deadline.get() {
+            events.insert(
+                "round".into(),
+                (GameIntent::EndRound, IntentMetadata::new(None, deadline)),
+            );
+        }
+
+        events
+    }
+}
+
+impl Game {
+    fn record(&mut self, entry: String, metadata: &IntentMetadata) {
+        let mut log = self.log.get();
+        log.push(format!("{} at {}s", entry, metadata.timestamp.timestamp()));
+        self.log.set(log);
+    }
+}
+
+#[test]
+fn events_fire_in_order_on_virtual_clock() {
+    let start = Utc.timestamp_opt(0, 0).unwrap();
+    let server = ServerConnection::<Game>::new();
+    let mut clock = VirtualClock::new(start);
+
+    server
+        .apply_system_intent(&GameIntent::StartRound(start))
+        .unwrap();
+
+    assert!(clock.advance(&server, Duration::seconds(5)).is_empty());
+    assert_eq!(
+        clock.advance(&server, Duration::seconds(6)),
+        vec!["turn:alice"]
+    );
+    assert_eq!(clock.now(), start + Duration::seconds(11));
+
+    // Bob passes before his clock runs out, cancelling it.
+    server
+        .apply_system_intent(&GameIntent::Pass("bob".into()))
+        .unwrap();
+
+    assert_eq!(
+        clock.advance(&server, Duration::seconds(100)),
+        vec!["turn:alice", "turn:alice", "round"]
+    );
+    assert_eq!(
+        server.state().log.get(),
+        vec![
+            "alice timed out at 10s",
+            "alice timed out at 30s",
+            "alice timed out at 50s",
+            "round over at 60s",
+        ]
+    );
+
+    // Nothing is scheduled once the round is over.
+    assert!(clock.advance(&server, Duration::seconds(100)).is_empty());
+}
+
+#[test]
+fn events_due_at_the_same_time_fire_once_each() {
+    let start = Utc.timestamp_opt(0, 0).unwrap();
+    let server = ServerConnection::<Game>::new();
+    let mut clock = VirtualClock::new(start);
+
+    server
+        .apply_system_intent(&GameIntent::StartRound(start - Duration::seconds(45)))
+        .unwrap();
+
+    // Alice's clock ran out at -35s and is rescheduled for -15s, then 5s; Bob's ran out at
+    // -30s and is rescheduled for -10s, then 10s.
+    assert_eq!(
+        clock.advance(&server, Duration::zero()),
+        vec!["turn:alice", "turn:bob", "turn:alice", "turn:bob"]
+    );
+    assert_eq!(
+        clock.advance(&server, Duration::seconds(10)),
+        vec!["turn:alice", "turn:bob"]
+    );
+}
+
+/// A tick that schedules the next tick as soon as it is applied, and is rejected while
+/// paused.
+#[derive(AperSync, Clone)]
+struct Ticker {
+    due: Atom<Option<Timestamp>>,
+    ticks: Atom<u32>,
+    paused: Atom<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum TickerIntent {
+    Start(Timestamp),
+    Pause(bool),
+    Tick,
+}
+
+impl Aper for Ticker {
+    type Intent = TickerIntent;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &TickerIntent, _metadata: &IntentMetadata) -> Result<(), ()> {
+        match intent {
+            TickerIntent::Start(due) => self.due.set(Some(*due)),
+            TickerIntent::Pause(paused) => self.paused.set(*paused),
+            TickerIntent::Tick if self.paused.get() => return Err(()),
+            TickerIntent::Tick => self.ticks.set(self.ticks.get() + 1),
+        }
+
+        Ok(())
+    }
+
+    fn scheduled_events(&self) -> BTreeMap<String, (TickerIntent, IntentMetadata)> {
+        self.due
+            .get()
+            .map(|due| {
+                (
+                    format!("tick:{}", self.ticks.get()),
+                    (TickerIntent::Tick, IntentMetadata::new(None, due)),
+                )
+            })
+            .into_iter()
+            .collect()
+    }
+}
+
+#[test]
+fn events_scheduled_while_running_wait_for_the_next_run() {
+    let start = Utc.timestamp_opt(0, 0).unwrap();
+    let server = ServerConnection::<Ticker>::new();
+    let mut scheduler = Scheduler::new();
+
+    server
+        .apply_system_intent(&TickerIntent::Start(start))
+        .unwrap();
+
+    assert_eq!(scheduler.run_due(&server, start), vec!["tick:0"]);
+    assert_eq!(scheduler.next_event(), Some(start));
+    assert_eq!(scheduler.run_due(&server, start), vec!["tick:1"]);
+    assert_eq!(server.state().ticks.get(), 2);
+
+    // The virtual clock gives up rather than running forever.
+    let mut clock = VirtualClock::new(start);
+    let fired = clock.advance(&server, Duration::seconds(1));
+    assert_eq!(fired.len(), MAX_RUNS_AT_ONCE);
+    assert_eq!(clock.now(), start + Duration::seconds(1));
+}
+
+#[test]
+fn rejected_events_are_not_retried() {
+    let start = Utc.timestamp_opt(0, 0).unwrap();
+    let server = ServerConnection::<Ticker>::new();
+    let mut scheduler = Scheduler::new();
+
+    server
+        .apply_system_intent(&TickerIntent::Start(start))
+        .unwrap();
+    server
+        .apply_system_intent(&TickerIntent::Pause(true))
+        .unwrap();
+
+    assert!(scheduler.run_due(&server, start).is_empty());
+    assert_eq!(scheduler.next_event(), None);
+
+    server
+        .apply_system_intent(&TickerIntent::Pause(false))
+        .unwrap();
+    assert!(scheduler.run_due(&server, start).is_empty());
+    assert_eq!(server.state().ticks.get(), 0);
+
+    // Scheduling it again at a different time does retry it.
+    let later = start + Duration::seconds(1);
+    server
+        .apply_system_intent(&TickerIntent::Start(later))
+        .unwrap();
+    assert_eq!(scheduler.run_due(&server, later), vec!["tick:0"]);
+    assert_eq!(server.state().ticks.get(), 1);
+}
@@ -29,6 +29,8 @@ impl Aper for Counter {
     type Intent = CounterIntent;
     type Error = ();
 
+    const SCHEMA_VERSION: u32 = 1;
+
     fn apply(
         &mut self,
         intent: &Self::Intent,
@@ -116,6 +118,33 @@ fn test_speculative_change_remains() {
     assert_eq!(15, state.get());
 }
 
+#[test]
+fn test_stacked_speculative_changes_remain() {
+    // client makes two speculative changes, and the server confirms only the first. The
+    // second should still be applied on top of the confirmed state.
+
+    let mut server = AperServer::<Counter>::new();
+    let mut client = AperClient::<Counter>::new();
+
+    let version = client
+        .apply(&CounterIntent::IncrementBy(5), &IntentMetadata::now())
+        .unwrap();
+    client
+        .apply(&CounterIntent::IncrementBy(7), &IntentMetadata::now())
+        .unwrap();
+
+    let mutations = server
+        .apply(&CounterIntent::IncrementBy(5), &IntentMetadata::now())
+        .unwrap();
+    client.mutate(&mutations, Some(version), 1);
+
+    assert_eq!(1, client.verified_client_version());
+    assert_eq!(2, client.speculative_client_version());
+
+    let state = client.state();
+    assert_eq!(12, state.get());
+}
+
 #[test]
 fn test_remote_changes_persist() {
     let mut server = AperServer::<Counter>::new();
@@ -137,3 +166,36 @@ fn test_remote_changes_persist() {
     let state = client.state();
     assert_eq!(10, state.get());
 }
+
+#[test]
+fn test_restore_from_snapshot() {
+    let mut server = AperServer::<Counter>::new();
+    let mut client = AperClient::<Counter>::new();
+
+    let mutations = server
+        .apply(&CounterIntent::IncrementBy(5), &IntentMetadata::now())
+        .unwrap();
+    client.mutate(&mutations, None, server.version());
+    server
+        .apply(&CounterIntent::IncrementBy(2), &IntentMetadata::now())
+        .unwrap();
+
+    let snapshot = bincode::serialize(&server.snapshot()).unwrap();
+    drop(server);
+
+    let snapshot = bincode::deserialize(&snapshot).unwrap();
+    let mut server = AperServer::<Counter>::from_snapshot(&snapshot);
+
+    assert_eq!(2, server.version());
+    assert_eq!(7, server.state().get());
+
+    // The restored server continues from the same version.
+    server
+        .apply(&CounterIntent::IncrementBy(3), &IntentMetadata::now())
+        .unwrap();
+    assert_eq!(3, server.version());
+
+    client.mutate(&server.state_snapshot(), None, server.version());
+    assert_eq!(10, client.state().get());
+    assert_eq!(3, client.verified_server_version());
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use aper::{connection::ServerConnection, data_structures::Atom, Aper, AperSync, IntentMetadata};
+use common::TestClient;
+use serde::{Deserialize, Serialize};
+
+#[derive(AperSync, Clone)]
+struct Round {
+    number: Atom<u32>,
+    started_by: Atom<Option<u32>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct NextRound;
+
+impl Aper for Round {
+    type Intent = NextRound;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, _intent: &NextRound, metadata: &IntentMetadata) -> Result<(), ()> {
+        self.number.set(self.number.get() + 1);
+        self.started_by.set(metadata.client);
+        Ok(())
+    }
+}
+
+#[test]
+fn system_intent_is_sent_to_every_client() {
+    let mut server = ServerConnection::<Round>::new();
+    let mut clients = [
+        TestClient::connect(&mut server),
+        TestClient::connect(&mut server),
+    ];
+
+    server.apply_system_intent(&NextRound).unwrap();
+    server.apply_system_intent(&NextRound).unwrap();
+
+    for client in clients.iter_mut() {
+        client.deliver();
+
+        assert_eq!(client.connection.state().number.get(), 2);
+        assert_eq!(client.connection.state().started_by.get(), None);
+        assert_eq!(client.connection.pending_intents(), 0);
+    }
+
+    assert_eq!(server.state().number.get(), 2);
+}
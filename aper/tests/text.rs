@@ -0,0 +1,64 @@
+use aper::{
+    data_structures::{Text, TextEdit},
+    Aper, AperClient, AperServer, AperSync, IntentMetadata,
+};
+
+#[derive(AperSync, Clone)]
+struct Document {
+    body: Text,
+}
+
+impl Aper for Document {
+    type Intent = TextEdit;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(
+        &mut self,
+        intent: &Self::Intent,
+        _metadata: &IntentMetadata,
+    ) -> Result<(), Self::Error> {
+        self.body.apply(intent);
+        Ok(())
+    }
+}
+
+#[test]
+fn concurrent_typing_through_server() {
+    let mut server = AperServer::<Document>::new();
+    let mut alice = AperClient::<Document>::new();
+    let mut bob = AperClient::<Document>::new();
+
+    let initial = server.state().body.insert_edit(0, "hello world", 1);
+    let mutations = server.apply(&initial, &IntentMetadata::now()).unwrap();
+    alice.mutate(&mutations, None, server.version());
+    bob.mutate(&mutations, None, server.version());
+
+    // Alice types at the start while Bob types at the end; Bob's caret is after "world".
+    let alice_edit = alice.state().body.insert_edit(0, "oh, ", 2);
+    let alice_version = alice.apply(&alice_edit, &IntentMetadata::now()).unwrap();
+
+    let bob_edit = bob.state().body.insert_edit(11, "!", 3);
+    let bob_version = bob.apply(&bob_edit, &IntentMetadata::now()).unwrap();
+    let bob_caret = bob.state().body.cursor(12);
+
+    let alice_mutations = server.apply(&alice_edit, &IntentMetadata::now()).unwrap();
+    let alice_server_version = server.version();
+    let bob_mutations = server.apply(&bob_edit, &IntentMetadata::now()).unwrap();
+    let bob_server_version = server.version();
+
+    alice.mutate(&alice_mutations, Some(alice_version), alice_server_version);
+    bob.mutate(&alice_mutations, None, alice_server_version);
+
+    // Bob's pending edit is re-applied on top of Alice's, and his caret moves with the text.
+    assert_eq!(bob.state().body.value(), "oh, hello world!");
+    assert_eq!(bob.state().body.index(&bob_caret), 16);
+
+    alice.mutate(&bob_mutations, None, bob_server_version);
+    bob.mutate(&bob_mutations, Some(bob_version), bob_server_version);
+
+    assert_eq!(server.state().body.value(), "oh, hello world!");
+    assert_eq!(alice.state().body.value(), "oh, hello world!");
+    assert_eq!(bob.state().body.value(), "oh, hello world!");
+}
@@ -56,6 +56,8 @@ impl Aper for TicTacToe {
     type Intent = TicTacToePlay;
     type Error = ();
 
+    const SCHEMA_VERSION: u32 = 1;
+
     fn apply(
         &mut self,
         intent: &Self::Intent,
@@ -0,0 +1,96 @@
+use aper::{
+    codec::WireFormat,
+    connection::ServerConnection,
+    data_structures::Atom,
+    transport::{channel_transport, ClientTransport, ServerTransport, TransportError},
+    Aper, AperClient, AperSync, IntentMetadata,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(AperSync, Clone)]
+struct Counter {
+    value: Atom<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct Increment;
+
+impl Aper for Counter {
+    type Intent = Increment;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, _intent: &Increment, _metadata: &IntentMetadata) -> Result<(), ()> {
+        self.value.set(self.value.get() + 1);
+        Ok(())
+    }
+}
+
+#[test]
+fn clients_sync_over_channel_transports() {
+    let mut server = ServerConnection::<Counter>::new();
+
+    let (alice_transport, server_transport) = channel_transport();
+    let mut alice_server = ServerTransport::new(&mut server, server_transport);
+    let mut alice = ClientTransport::new(AperClient::<Counter>::new(), alice_transport);
+
+    let (bob_transport, server_transport) = channel_transport();
+    let mut bob_server = ServerTransport::new(&mut server, server_transport);
+    let mut bob = ClientTransport::with_wire_format(
+        AperClient::<Counter>::new(),
+        WireFormat::Json,
+        bob_transport,
+    );
+
+    alice.connection_mut().apply(Increment).unwrap();
+    bob.connection_mut().apply(Increment).unwrap();
+
+    alice_server.process().unwrap();
+    bob_server.process().unwrap();
+    alice.process().unwrap();
+    bob.process().unwrap();
+
+    assert_eq!(server.state().value.get(), 2);
+    assert_eq!(alice.connection().state().value.get(), 2);
+    assert_eq!(bob.connection().state().value.get(), 2);
+    assert_eq!(
+        alice.connection().client_id(),
+        Some(alice_server.handle().client_id())
+    );
+    assert_eq!(bob_server.handle().wire_format(), WireFormat::Json);
+
+    bob.close();
+    assert_eq!(bob_server.process(), Err(TransportError::Closed));
+}
+
+#[test]
+fn client_resumes_over_new_transport() {
+    let mut server = ServerConnection::<Counter>::new();
+
+    let (client_transport, server_transport) = channel_transport();
+    let mut alice_server = ServerTransport::new(&mut server, server_transport);
+    let mut alice = ClientTransport::new(AperClient::<Counter>::new(), client_transport);
+    alice_server.process().unwrap();
+    alice.process().unwrap();
+    let client_id = alice.connection().client_id();
+
+    // The connection drops, and an intent is applied before it is restored.
+    alice.close();
+    assert_eq!(alice_server.process(), Err(TransportError::Closed));
+    drop(alice_server);
+    alice.connection_mut().apply(Increment).unwrap();
+
+    let (client_transport, server_transport) = channel_transport();
+    let mut alice_server = ServerTransport::new(&mut server, server_transport);
+    alice.reconnect(client_transport);
+    alice_server.process().unwrap();
+    alice.process().unwrap();
+    alice_server.process().unwrap();
+    alice.process().unwrap();
+
+    assert_eq!(alice.connection().client_id(), client_id);
+    assert_eq!(server.state().value.get(), 1);
+    assert_eq!(alice.connection().state().value.get(), 1);
+    assert_eq!(alice.connection().pending_intents(), 0);
+}
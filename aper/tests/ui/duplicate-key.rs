@@ -0,0 +1,17 @@
+use aper::{data_structures::Atom, AperSync};
+
+#[derive(AperSync, Clone)]
+struct Settings {
+    volume: Atom<u8>,
+    #[aper(rename = "volume")]
+    master_volume: Atom<u8>,
+}
+
+#[derive(AperSync, Clone)]
+enum Mode {
+    Edit,
+    #[aper(rename = "Edit")]
+    Editing,
+}
+
+fn main() {}
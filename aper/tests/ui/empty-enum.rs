@@ -0,0 +1,6 @@
+use aper::AperSync;
+
+#[derive(AperSync, Clone)]
+enum Nothing {}
+
+fn main() {}
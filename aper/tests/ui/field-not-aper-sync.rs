@@ -0,0 +1,20 @@
+use aper::{data_structures::Atom, AperSync, StoreHandle};
+
+#[derive(Clone)]
+struct Score<T>(T);
+
+// Only implemented for one type, so that the error names it instead of listing every
+// implementation of `AperSync`.
+impl AperSync for Score<u64> {
+    fn attach(_map: StoreHandle) -> Self {
+        Score(0)
+    }
+}
+
+#[derive(AperSync, Clone)]
+struct Player {
+    name: Atom<String>,
+    score: Score<u32>,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use aper::AperSync;
+
+#[derive(AperSync, Clone, Copy)]
+union Value {
+    int: u32,
+    float: f32,
+}
+
+fn main() {}
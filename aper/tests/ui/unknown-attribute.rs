@@ -0,0 +1,15 @@
+use aper::{data_structures::Atom, AperSync};
+
+#[derive(AperSync, Clone)]
+struct Settings {
+    #[aper(default)]
+    volume: Atom<u8>,
+}
+
+#[derive(AperSync, Clone)]
+enum Mode {
+    #[aper(skip)]
+    Edit,
+}
+
+fn main() {}
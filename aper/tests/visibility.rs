@@ -0,0 +1,168 @@
+mod common;
+
+use aper::{
+    connection::{MessageToClientType, ServerConnection},
+    data_structures::{Atom, Map},
+    Aper, AperSync, Bytes, ClientIdentity, IntentMetadata,
+};
+use common::TestClient;
+use serde::{Deserialize, Serialize};
+
+#[derive(AperSync, Clone)]
+struct CardGame {
+    hands: Map<String, Atom<Vec<u32>>>,
+    table: Atom<Vec<u32>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+enum CardIntent {
+    Deal(String, Vec<u32>),
+    Play(String, u32),
+}
+
+impl Aper for CardGame {
+    type Intent = CardIntent;
+    type Error = ();
+
+    const SCHEMA_VERSION: u32 = 1;
+
+    fn apply(&mut self, intent: &CardIntent, _metadata: &IntentMetadata) -> Result<(), ()> {
+        match intent {
+            CardIntent::Deal(player, cards) => {
+                self.hands.get_or_create(player).set(cards.clone());
+            }
+            CardIntent::Play(player, card) => {
+                let mut hand = self.hands.get_or_create(player);
+                let mut cards = hand.get();
+                cards.retain(|c| c != card);
+                hand.set(cards);
+
+                let mut table = self.table.get();
+                table.push(*card);
+                self.table.set(table);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Each player can only see their own hand.
+fn visible(prefix: &[Bytes], _client_id: u32, identity: &ClientIdentity) -> bool {
+    match prefix {
+        [field, player, ..] if field.as_ref() == b"hands" => identity
+            .user
+            .as_ref()
+            .is_some_and(|user| player.as_ref() == bincode::serialize(user).unwrap().as_slice()),
+        _ => true,
+    }
+}
+
+fn connect(server: &mut ServerConnection<CardGame>, name: &str) -> TestClient<CardGame> {
+    TestClient::connect_as(server, ClientIdentity::user(name))
+}
+
+/// Whether any message received by `client` mentions `player`'s hand.
+fn has_seen_hand(client: &TestClient<CardGame>, player: &str) -> bool {
+    let player = bincode::serialize(player).unwrap();
+    client.received.lock().unwrap().iter().any(|message| {
+        let mutations = match &message.message {
+            MessageToClientType::Apply { mutations, .. } => mutations,
+            MessageToClientType::Resumed { mutations, .. } => mutations,
+            _ => return false,
+        };
+
+        mutations.iter().any(|mutation| {
+            mutation.prefix.len() >= 2 && mutation.prefix[1].as_ref() == player.as_slice()
+        })
+    })
+}
+
+#[test]
+fn players_only_see_their_own_hand() {
+    let mut server = ServerConnection::<CardGame>::new();
+    server.set_visibility(visible);
+
+    let mut alice = connect(&mut server, "alice");
+    let mut bob = connect(&mut server, "bob");
+
+    alice
+        .connection
+        .apply(CardIntent::Deal("alice".into(), vec![1, 2, 3]))
+        .unwrap();
+    alice.deliver();
+    bob.connection
+        .apply(CardIntent::Deal("bob".into(), vec![4, 5, 6]))
+        .unwrap();
+    bob.deliver();
+    alice.deliver();
+
+    alice
+        .connection
+        .apply(CardIntent::Play("alice".into(), 2))
+        .unwrap();
+    alice.deliver();
+    bob.deliver();
+
+    assert_eq!(
+        alice
+            .connection
+            .state()
+            .hands
+            .get_or_create(&"alice".into())
+            .get(),
+        vec![1, 3]
+    );
+    assert_eq!(
+        bob.connection
+            .state()
+            .hands
+            .get_or_create(&"bob".into())
+            .get(),
+        vec![4, 5, 6]
+    );
+    assert_eq!(bob.connection.state().table.get(), vec![2]);
+
+    assert!(!has_seen_hand(&alice, "bob"));
+    assert!(!has_seen_hand(&bob, "alice"));
+    assert_eq!(
+        alice
+            .connection
+            .state()
+            .hands
+            .get_or_create(&"bob".into())
+            .get(),
+        Vec::<u32>::new()
+    );
+
+    // A late joiner's snapshot is filtered too.
+    let carol = connect(&mut server, "carol");
+    assert_eq!(carol.connection.state().table.get(), vec![2]);
+    assert!(!has_seen_hand(&carol, "alice"));
+    assert!(!has_seen_hand(&carol, "bob"));
+
+    // Everything is still on the server.
+    assert_eq!(
+        server.state().hands.get_or_create(&"bob".into()).get(),
+        vec![4, 5, 6]
+    );
+}
+
+#[test]
+fn visibility_applies_to_connected_clients() {
+    let mut server = ServerConnection::<CardGame>::new();
+    let mut alice = connect(&mut server, "alice");
+    let mut bob = connect(&mut server, "bob");
+
+    server.set_visibility(visible);
+
+    alice
+        .connection
+        .apply(CardIntent::Deal("alice".into(), vec![1, 2, 3]))
+        .unwrap();
+    alice.deliver();
+    bob.deliver();
+
+    assert!(has_seen_hand(&alice, "alice"));
+    assert!(!has_seen_hand(&bob, "alice"));
+}
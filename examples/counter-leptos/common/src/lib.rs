@@ -23,6 +23,8 @@ impl Aper for Counter {
     type Intent = CounterIntent;
     type Error = ();
 
+    const SCHEMA_VERSION: u32 = 1;
+
     fn apply(&mut self, intent: &CounterIntent, _metadata: &IntentMetadata) -> Result<(), ()> {
         let value = self.value.get();
 
@@ -154,6 +154,8 @@ impl Aper for DropFourGame {
     type Intent = GameTransition;
     type Error = ();
 
+    const SCHEMA_VERSION: u32 = 1;
+
     fn apply(&mut self, event: &IntentEvent<Self::Intent>) -> Result<(), ()> {
         match event.intent {
             GameTransition::Join => {
@@ -18,6 +18,8 @@ impl Aper for Timer {
     type Intent = TimerIntent;
     type Error = ();
 
+    const SCHEMA_VERSION: u32 = 1;
+
     fn apply(&mut self, event: &IntentEvent<Self::Intent>) -> Result<(), ()> {
         match event.intent {
             TimerIntent::Reset => self.value.set(0),